@@ -0,0 +1,152 @@
+use crate::config::{ServiceConfig, WorkspaceConfig};
+use crate::local::{self, Image, Running};
+use crate::metadata::Workspace;
+use crate::{table, BenchStartArgs};
+use std::error::Error;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// What gets started on every run.
+enum Program {
+    Binary(PathBuf),
+    Image(Image),
+}
+
+/// Start the service over and over and report how long it takes to listen and to answer.
+pub fn handle_bench_start(args: &BenchStartArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = if args.container { Workspace::load_locked()? } else { Workspace::load()? };
+    let pkg = workspace.find_root_package()?;
+    let mut config = ServiceConfig::load(pkg, args.environment.as_deref())?;
+
+    let program = match args.container {
+        true => Program::Image(Image::build(&workspace, pkg, &config)?),
+        false => {
+            config.build.sccache = WorkspaceConfig::load(&workspace)?.sccache;
+            Program::Binary(local::build(&workspace, pkg, &config.build)?)
+        }
+    };
+    let timeout = Duration::from_secs(args.timeout);
+
+    println!("Starting '{}' {} times", config.service, args.runs);
+    let mut to_listen = Vec::new();
+    let mut to_respond = Vec::new();
+    for run in 1..=args.runs {
+        let port = local::free_port()?;
+        let container = format!("cloudrun-bench-{}-{run}", std::process::id());
+        let started = Instant::now();
+        let (mut running, stderr) = start(&program, &config, port, &container)?;
+        let result = measure(port, &mut running, &args.path, started, timeout);
+        running.stop();
+        let stderr = stderr.join().unwrap_or_default();
+
+        let (listening, responding) = result.map_err(|err| {
+            let tail = stderr.lines().rev().take(20).collect::<Vec<_>>();
+            let tail = tail.into_iter().rev().collect::<Vec<_>>().join("\n");
+            format!("Run {run}: {err}\n{tail}")
+        })?;
+        println!(
+            "Run {run}: listening after {}, first response after {}",
+            millis(listening),
+            millis(responding)
+        );
+        to_listen.push(listening);
+        to_respond.push(responding);
+    }
+
+    println!();
+    let row = |name: &str, samples: &mut Vec<Duration>| {
+        samples.sort();
+        vec![
+            name.to_string(),
+            millis(percentile(samples, 50)),
+            millis(percentile(samples, 95)),
+            millis(samples[0]),
+            millis(samples[samples.len() - 1]),
+        ]
+    };
+    table::print(
+        &["", "P50", "P95", "MIN", "MAX"],
+        &[
+            row("time to listen", &mut to_listen),
+            row("time to first response", &mut to_respond),
+        ],
+    );
+    Ok(())
+}
+
+/// Start the program with Cloud Run's env on `port`. Its stderr is collected in the background.
+fn start(
+    program: &Program,
+    config: &ServiceConfig,
+    port: u16,
+    container: &str,
+) -> Result<(Running, JoinHandle<String>), Box<dyn Error>> {
+    let (mut command, container) = match program {
+        Program::Binary(path) => {
+            let mut command = Command::new(path);
+            command.envs(local::cloud_run_env(config, port));
+            (command, None)
+        }
+        Program::Image(image) => (
+            image.run_command(config, port, container, &[])?,
+            Some((image.engine, container.to_string())),
+        ),
+    };
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let collector = thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output);
+        output
+    });
+    Ok((Running { child, container }, collector))
+}
+
+/// Time until the port accepts connections and until `GET path` gets a response below 500.
+/// A container counts as listening once it answers at all, its engine's proxy accepts
+/// connections before the service does.
+fn measure(
+    port: u16,
+    running: &mut Running,
+    path: &str,
+    started: Instant,
+    timeout: Duration,
+) -> Result<(Duration, Duration), Box<dyn Error>> {
+    let mut listening = None;
+    if running.container.is_none() {
+        local::wait_for_port(port, &mut running.child, timeout)?;
+        listening = Some(started.elapsed());
+    }
+    loop {
+        if let Some(status) = local::http_get(port, path) {
+            let listening = *listening.get_or_insert_with(|| started.elapsed());
+            if status < 500 {
+                return Ok((listening, started.elapsed()));
+            }
+        }
+        if let Some(status) = running.child.try_wait()? {
+            return Err(format!("The service exited with {status} before answering").into());
+        }
+        if started.elapsed() > timeout {
+            return Err(format!("No successful response to GET {path} within {}s", timeout.as_secs()).into());
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty `samples`.
+fn percentile(samples: &[Duration], p: usize) -> Duration {
+    let rank = (p * samples.len()).div_ceil(100).max(1);
+    samples[rank - 1]
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,52 @@
+use crate::config::ServiceConfig;
+use crate::gcloud;
+
+/// Printed by the generated Dockerfile after the build, followed by the binary's size in bytes.
+pub const BINARY_SIZE_MARKER: &str = "cargo-cloudrun: binary size";
+
+/// The Cloud Build log of a `gcloud run deploy --source`, found through the link in its output.
+pub fn fetch(config: &ServiceConfig, lines: &[String]) -> Option<String> {
+    // e.g. https://console.cloud.google.com/cloud-build/builds;region=europe-west1/<id>?project=123
+    let link = lines.iter().find_map(|line| line.split_once("cloud-build/builds"))?.1;
+    let link = link.split(['?', ']', ' ']).next()?;
+    let (region, id) = match link.split_once('/') {
+        Some((location, id)) => (location.strip_prefix(";region="), id),
+        None => (None, link.trim_start_matches('/')),
+    };
+
+    let mut args = vec!["builds".to_string(), "log".to_string(), id.to_string()];
+    if let Some(region) = region {
+        args.push(format!("--region={region}"));
+    }
+    if let Some(project) = &config.project {
+        args.push(format!("--project={project}"));
+    }
+    gcloud::text(&args).ok()
+}
+
+/// The cache hit rate from the output of `sccache --show-stats` in a build log.
+pub fn sccache_stats(log: &str) -> Option<String> {
+    // Lines may be prefixed with the build step, e.g. `Step #0: Cache hits rate   83.33 %`
+    let stat = |name: &str| {
+        log.lines().rev().find_map(|line| {
+            let value = line.split_once(name)?.1;
+            let is_value = value.starts_with(char::is_whitespace)
+                && value.trim_start().starts_with(|c: char| c.is_ascii_digit() || c == '-');
+            is_value.then(|| value.trim().to_string())
+        })
+    };
+    Some(format!(
+        "{} cache hits ({} hits, {} misses)",
+        stat("Cache hits rate")?,
+        stat("Cache hits").unwrap_or_default(),
+        stat("Cache misses").unwrap_or_default()
+    ))
+}
+
+/// The size of the binary the generated Dockerfile reported.
+pub fn binary_size(log: &str) -> Option<u64> {
+    log.lines()
+        .rev()
+        .find_map(|line| line.split_once(BINARY_SIZE_MARKER))
+        .and_then(|(_, size)| size.trim().parse().ok())
+}
@@ -0,0 +1,188 @@
+use crate::config::{BuildCache, CloudBuildConfig, ServiceConfig};
+use crate::metadata::package_name;
+use crate::output::{self, Prefix};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Cloud Build's default of 10 minutes is too short for most release builds.
+const DEFAULT_TIMEOUT: &str = "3600s";
+
+/// Stage of the Dockerfile the tests run in. The generated Dockerfile builds in it, with the
+/// packages, toolchain, env and sources the release build gets.
+const TEST_STAGE: &str = "build-env";
+
+/// Tag of the test stage's image, only known to the docker daemon of the build.
+const TEST_IMAGE: &str = "cloudrun-test";
+
+/// Cloud Build's docker builder.
+const DOCKER: &str = "gcr.io/cloud-builders/docker";
+
+/// An image built by Cloud Build, along with everything the build printed.
+pub struct Build {
+    pub image: String,
+    pub lines: Vec<String>,
+}
+
+/// Render `cloudbuild.yaml` for the build context of `pkg`, write it next to the context and
+/// run it with `gcloud builds submit`. The image is tagged with `source_hash`, which is the tag
+/// that gets deployed, the git SHA and the configured tags.
+pub fn submit(
+    context: &Path,
+    root_dir: &Path,
+    pkg: &Value,
+    config: &ServiceConfig,
+    tags: &[String],
+    prefix: Option<&Prefix>,
+) -> Result<Build, Box<dyn Error>> {
+    let cloud_build = config.cloud_build.clone().unwrap_or_default();
+    let Some(registry) = config.registry() else {
+        return Err("Building with Cloud Build needs a `registry`, or a `project` and `region`".into());
+    };
+    let repository = format!("{registry}/{}", config.service);
+    let mut tags = tags.to_vec();
+    tags.extend(cloud_build.tags.iter().cloned());
+    let images = tags.iter().map(|tag| format!("{repository}:{tag}")).collect::<Vec<_>>();
+
+    if !cloud_build.test.is_empty() && !has_test_stage(&context.join("Dockerfile")) {
+        return Err(format!(
+            "Cloud Build `test` commands run in the `{TEST_STAGE}` stage of the Dockerfile, which the \
+             Dockerfile of '{}' does not have",
+            package_name(pkg)
+        )
+        .into());
+    }
+    let yaml = render(&cloud_build, &repository, &images);
+    let path = config_path(root_dir, &config.service);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, yaml)?;
+
+    let mut command = Command::new("gcloud");
+    command
+        .args(["builds", "submit", "."])
+        .arg(format!("--config={}", path.display()))
+        .current_dir(context);
+    if let Some(project) = &config.project {
+        command.arg(format!("--project={project}"));
+    }
+    let (status, lines) = output::run_captured(&mut command, prefix)
+        .map_err(|err| format!("Failed to spawn gcloud process: {err}"))?;
+    if !status.success() {
+        return Err(format!("gcloud builds submit failed with status: {:?}", status.code()).into());
+    }
+    Ok(Build {
+        image: images[0].clone(),
+        lines,
+    })
+}
+
+/// Where the rendered config of a service goes. Outside of any build context, so it does
+/// not change the source hash.
+pub fn config_path(root_dir: &Path, service: &str) -> PathBuf {
+    root_dir
+        .join("target")
+        .join("cloudrun")
+        .join(format!("{service}.cloudbuild.yaml"))
+}
+
+/// Whether the Dockerfile at `path` has a stage named [`TEST_STAGE`].
+fn has_test_stage(path: &Path) -> bool {
+    let dockerfile = fs::read_to_string(path).unwrap_or_default();
+    dockerfile.lines().any(|line| {
+        let words = line.split_whitespace().map(str::to_ascii_lowercase).collect::<Vec<_>>();
+        words.first().is_some_and(|w| w == "from") && words.ends_with(&["as".to_string(), TEST_STAGE.to_string()])
+    })
+}
+
+/// The `cloudbuild.yaml`: test steps in the build stage of the Dockerfile, then the image build
+/// and push.
+fn render(cloud_build: &CloudBuildConfig, repository: &str, images: &[String]) -> String {
+    let mut yaml = String::from("# Generated by cargo-cloudrun\nsteps:\n");
+    if !cloud_build.test.is_empty() {
+        let tag = format!("--tag={TEST_IMAGE}");
+        let target = format!("--target={TEST_STAGE}");
+        step(&mut yaml, "test-image", DOCKER, None, &["build", "--network=cloudbuild", &target, &tag, "."]);
+    }
+    for (i, test) in cloud_build.test.iter().enumerate() {
+        let args = ["run", "--rm", "--network=cloudbuild", TEST_IMAGE, "bash", "-c", test];
+        step(&mut yaml, &format!("test-{}", i + 1), DOCKER, None, &args);
+    }
+
+    let tags = images.iter().map(|image| format!("--destination={image}"));
+    match cloud_build.cache {
+        Some(BuildCache::Kaniko) => {
+            let mut args = tags.collect::<Vec<_>>();
+            args.extend([
+                "--dockerfile=Dockerfile".to_string(),
+                "--context=dir:///workspace".to_string(),
+                "--cache=true".to_string(),
+                format!("--cache-repo={repository}/cache"),
+            ]);
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            step(&mut yaml, "build", "gcr.io/kaniko-project/executor:latest", None, &args);
+        }
+        Some(BuildCache::Buildx) => {
+            let cache = format!("{repository}:buildcache");
+            let tags = images.iter().map(|image| format!("-t {image}")).collect::<Vec<_>>().join(" ");
+            let script = format!(
+                "docker buildx create --use --driver-opt network=cloudbuild \
+                 && docker buildx build --push {tags} \
+                 --cache-from type=registry,ref={cache} --cache-to type=registry,ref={cache},mode=max ."
+            );
+            step(&mut yaml, "build", DOCKER, Some("bash"), &["-c", &script]);
+        }
+        None => {
+            let mut args = vec!["build".to_string(), "--network=cloudbuild".to_string()];
+            for image in images {
+                args.push(format!("--tag={image}"));
+            }
+            args.push(".".to_string());
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            step(&mut yaml, "build", DOCKER, None, &args);
+            // Kaniko and buildx push themselves
+            yaml.push_str("images:\n");
+            for image in images {
+                let _ = writeln!(yaml, "  - {}", quote(image));
+            }
+        }
+    }
+
+    let _ = writeln!(
+        yaml,
+        "timeout: {}",
+        quote(cloud_build.timeout.as_deref().unwrap_or(DEFAULT_TIMEOUT))
+    );
+    if cloud_build.machine_type.is_some() || cloud_build.disk_size.is_some() {
+        yaml.push_str("options:\n");
+        if let Some(machine_type) = &cloud_build.machine_type {
+            let _ = writeln!(yaml, "  machineType: {}", quote(machine_type));
+        }
+        if let Some(disk_size) = cloud_build.disk_size {
+            let _ = writeln!(yaml, "  diskSizeGb: {disk_size}");
+        }
+    }
+    yaml
+}
+
+fn step(yaml: &mut String, id: &str, image: &str, entrypoint: Option<&str>, args: &[&str]) {
+    let _ = writeln!(yaml, "  - id: {}", quote(id));
+    let _ = writeln!(yaml, "    name: {}", quote(image));
+    if let Some(entrypoint) = entrypoint {
+        let _ = writeln!(yaml, "    entrypoint: {}", quote(entrypoint));
+    }
+    yaml.push_str("    args:\n");
+    for arg in args {
+        let _ = writeln!(yaml, "      - {}", quote(arg));
+    }
+}
+
+/// A double-quoted YAML string (JSON strings are valid YAML), with `$` escaped so Cloud Build
+/// does not take shell variables for substitutions.
+fn quote(value: &str) -> String {
+    Value::String(value.replace('$', "$$")).to_string()
+}
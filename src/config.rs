@@ -0,0 +1,560 @@
+use crate::metadata::{package_name, Workspace};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Deployment settings for a single service, read from `[package.metadata.cloudrun]`.
+///
+/// Named environments live under `[package.metadata.cloudrun.environments.<name>]` and
+/// override the top-level keys when selected, e.g.
+///
+/// ```toml
+/// [package.metadata.cloudrun]
+/// region = "europe-west1"
+/// memory = "512Mi"
+///
+/// [package.metadata.cloudrun.environments.production]
+/// project = "acme-prod"
+/// min-instances = 1
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub service: String,
+    pub project: Option<String>,
+    pub region: Option<String>,
+    /// Artifact Registry repository images are stored in, e.g. `europe-docker.pkg.dev/acme/services`.
+    pub registry: Option<String>,
+    pub memory: Option<String>,
+    pub cpu: Option<String>,
+    pub min_instances: Option<u64>,
+    pub max_instances: Option<u64>,
+    pub concurrency: Option<u64>,
+    pub timeout: Option<String>,
+    pub service_account: Option<String>,
+    pub allow_unauthenticated: bool,
+    pub http2: bool,
+    pub env: BTreeMap<String, String>,
+    /// Services (by service or package name) this one calls. Their URLs are injected as
+    /// `<SERVICE>_URL` env vars and this service is allowed to invoke them.
+    pub depends_on: Vec<String>,
+    pub build: BuildConfig,
+    /// Build with a rendered `cloudbuild.yaml` and deploy the image, instead of `--source`.
+    pub cloud_build: Option<CloudBuildConfig>,
+}
+
+/// Settings of the `cloudbuild.yaml` rendered for a deploy, from
+/// `[package.metadata.cloudrun.cloud-build]`.
+#[derive(Debug, Clone, Default)]
+pub struct CloudBuildConfig {
+    /// e.g. `E2_HIGHCPU_8`
+    pub machine_type: Option<String>,
+    /// Disk size in GB.
+    pub disk_size: Option<u64>,
+    /// Build timeout, e.g. `3600s`.
+    pub timeout: Option<String>,
+    pub cache: Option<BuildCache>,
+    /// Image tags in addition to the source hash and git SHA.
+    pub tags: Vec<String>,
+    /// Commands run in the builder image before the image is built, e.g. `cargo test --locked`.
+    pub test: Vec<String>,
+}
+
+/// How layers are cached between Cloud Build runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildCache {
+    /// Kaniko's layer cache in the registry.
+    Kaniko,
+    /// A BuildKit cache image in the registry.
+    Buildx,
+}
+
+/// How the generated Dockerfile builds and packages the service.
+#[derive(Debug, Clone, Default)]
+pub struct BuildConfig {
+    /// Target triple to build for, e.g. `x86_64-unknown-linux-musl`.
+    pub target: Option<String>,
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    /// Cargo profile to build with, `release` if not set.
+    pub profile: Option<String>,
+    pub rustflags: Option<String>,
+    /// Env vars set while building, but not in the runtime image.
+    pub env: BTreeMap<String, String>,
+    /// `ARG`s declared in the build stage with these defaults.
+    pub args: BTreeMap<String, String>,
+    /// Debian packages installed in the build stage, e.g. `protobuf-compiler`.
+    pub build_packages: Vec<String>,
+    /// Debian packages installed in the runtime image, e.g. `libpq5`.
+    pub runtime_packages: Vec<String>,
+    /// Base of the runtime image. Distroless cc unless runtime packages need Debian.
+    pub runtime: Option<Runtime>,
+    pub non_root: bool,
+    /// Ship time zone data with images that do not have it.
+    pub tzdata: bool,
+    /// A read-only root filesystem, which Cloud Run does not have. Rejected with an explanation.
+    pub read_only: bool,
+    /// Files and directories, relative to the package, copied next to the binary.
+    pub assets: Vec<String>,
+    /// Directory the binary and assets are copied to and run in, `/` if not set.
+    pub workdir: Option<String>,
+    /// Stages run before the cargo build, e.g. to build a frontend.
+    pub steps: Vec<BuildStep>,
+    /// Commands run instead of `cargo build`, e.g. `cargo leptos build --release`.
+    pub command: Vec<String>,
+    /// Paths, relative to the workspace root, the build produces besides the binary and that
+    /// are copied next to it, e.g. `target/site`.
+    pub outputs: Vec<String>,
+    /// Run `cargo vendor` locally and upload the dependencies with the sources, so private
+    /// git repositories and registries are fetched with local credentials.
+    pub vendor: bool,
+    /// Shared compilation cache, from the workspace config.
+    pub sccache: Option<Sccache>,
+    /// Build with thin LTO, `panic = "abort"` and stripped symbols, overriding the profile.
+    pub optimize: bool,
+}
+
+/// A pre-build step, rendered as its own Dockerfile stage.
+///
+/// ```toml
+/// [[package.metadata.cloudrun.steps]]
+/// name = "frontend"
+/// image = "node:20"
+/// dir = "frontend"
+/// run = ["npm ci", "npm run build"]
+/// outputs = ["dist"]
+/// ```
+#[derive(Debug, Clone)]
+pub struct BuildStep {
+    pub name: String,
+    pub image: String,
+    /// Directory, relative to the package, the commands run in.
+    pub dir: Option<String>,
+    pub run: Vec<String>,
+    /// Paths, relative to `dir`, copied into the cargo build and next to the binary.
+    pub outputs: Vec<String>,
+}
+
+/// Base image of the runtime stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Runtime {
+    /// `gcr.io/distroless/cc-debian12`, glibc and libgcc but no shell or package manager.
+    DistrolessCc,
+    /// `gcr.io/distroless/static-debian12`, for statically linked binaries.
+    DistrolessStatic,
+    /// An empty image, for statically linked binaries.
+    Scratch,
+    /// `debian:bookworm-slim`, with apt for runtime packages.
+    DebianSlim,
+}
+
+impl Runtime {
+    const ALL: [Runtime; 4] = [
+        Runtime::DistrolessCc,
+        Runtime::DistrolessStatic,
+        Runtime::Scratch,
+        Runtime::DebianSlim,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Runtime::DistrolessCc => "distroless-cc",
+            Runtime::DistrolessStatic => "distroless-static",
+            Runtime::Scratch => "scratch",
+            Runtime::DebianSlim => "debian-slim",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        Self::ALL.into_iter().find(|r| r.name() == name).ok_or_else(|| {
+            let names = Self::ALL.map(Runtime::name);
+            format!("Unknown runtime '{name}', expected one of {}", names.join(", ")).into()
+        })
+    }
+}
+
+impl BuildConfig {
+    /// Directory under `target/` the profile's artifacts end up in.
+    pub fn profile_dir(&self) -> &str {
+        match self.profile.as_deref() {
+            None | Some("release") | Some("bench") => "release",
+            Some("dev") | Some("test") => "debug",
+            Some(profile) => profile,
+        }
+    }
+
+    /// `CARGO_PROFILE_<PROFILE>_*` env vars applying `optimize`. Cargo reads profile settings
+    /// from the environment as well, overriding the manifest.
+    pub fn profile_env(&self) -> Vec<(String, String)> {
+        if !self.optimize {
+            return Vec::new();
+        }
+        let profile = self
+            .profile
+            .as_deref()
+            .unwrap_or("release")
+            .to_ascii_uppercase()
+            .replace('-', "_");
+        [("LTO", "thin"), ("PANIC", "abort"), ("STRIP", "symbols")]
+            .iter()
+            .map(|(key, value)| (format!("CARGO_PROFILE_{profile}_{key}"), value.to_string()))
+            .collect()
+    }
+}
+
+impl ServiceConfig {
+    /// Names of the environments defined for a `cargo metadata` package entry.
+    pub fn environments(pkg: &Value) -> Vec<String> {
+        pkg.pointer("/metadata/cloudrun/environments")
+            .and_then(Value::as_object)
+            .map(|environments| environments.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Read the config of a `cargo metadata` package entry, applying the overrides of
+    /// `environment` if one is given.
+    pub fn load(pkg: &Value, environment: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let base = pkg
+            .pointer("/metadata/cloudrun")
+            .cloned()
+            .unwrap_or_else(|| Value::Object(Map::new()));
+        let Value::Object(mut table) = base else {
+            return Err("`package.metadata.cloudrun` must be a table".into());
+        };
+        let environments = table.remove("environments");
+
+        if let Some(name) = environment {
+            let Some(overrides) = environments.as_ref().and_then(|e| e.get(name)) else {
+                return Err(format!(
+                    "Environment '{name}' is not defined in [package.metadata.cloudrun.environments] of '{}'",
+                    package_name(pkg)
+                )
+                .into());
+            };
+            let Value::Object(overrides) = overrides else {
+                return Err(format!("Environment '{name}' must be a table").into());
+            };
+            merge(&mut table, overrides);
+        }
+
+        Self::from_table(package_name(pkg), &table)
+    }
+
+    fn from_table(package_name: &str, table: &Map<String, Value>) -> Result<Self, Box<dyn Error>> {
+        Ok(ServiceConfig {
+            service: get_str(table, "service")?.unwrap_or_else(|| package_name.to_string()),
+            project: get_str(table, "project")?,
+            region: get_str(table, "region")?,
+            registry: get_str(table, "registry")?,
+            memory: get_str(table, "memory")?,
+            cpu: get_str(table, "cpu")?,
+            min_instances: get_u64(table, "min-instances")?,
+            max_instances: get_u64(table, "max-instances")?,
+            concurrency: get_u64(table, "concurrency")?,
+            timeout: get_str(table, "timeout")?,
+            service_account: get_str(table, "service-account")?,
+            allow_unauthenticated: get_bool(table, "allow-unauthenticated")?.unwrap_or(true),
+            http2: get_bool(table, "http2")?.unwrap_or(true),
+            env: get_map(table, "env")?,
+            depends_on: match get_list(table, "depends-on")? {
+                Some(list) => list,
+                None => get_list(table, "depends_on")?.unwrap_or_default(),
+            },
+            cloud_build: match table.get("cloud-build") {
+                None => None,
+                Some(Value::Object(cloud_build)) => Some(CloudBuildConfig {
+                    machine_type: get_str(cloud_build, "machine-type")?,
+                    disk_size: get_u64(cloud_build, "disk-size")?,
+                    timeout: get_str(cloud_build, "timeout")?,
+                    cache: match get_str(cloud_build, "cache")?.as_deref() {
+                        None => None,
+                        Some("kaniko") => Some(BuildCache::Kaniko),
+                        Some("buildx") => Some(BuildCache::Buildx),
+                        Some(other) => {
+                            return Err(format!("Unknown cache '{other}', expected \"kaniko\" or \"buildx\"").into())
+                        }
+                    },
+                    tags: get_list(cloud_build, "tags")?.unwrap_or_default(),
+                    test: get_commands(cloud_build, "test")?,
+                }),
+                Some(_) => return Err("`cloud-build` must be a table".into()),
+            },
+            build: BuildConfig {
+                target: get_str(table, "target")?,
+                features: get_list(table, "features")?.unwrap_or_default(),
+                no_default_features: get_bool(table, "no-default-features")?.unwrap_or(false),
+                profile: get_str(table, "profile")?,
+                rustflags: get_str(table, "rustflags")?,
+                env: get_map(table, "build-env")?,
+                args: get_map(table, "build-args")?,
+                build_packages: get_list(table, "build-packages")?.unwrap_or_default(),
+                runtime_packages: get_list(table, "runtime-packages")?.unwrap_or_default(),
+                runtime: get_str(table, "runtime")?.as_deref().map(Runtime::parse).transpose()?,
+                non_root: get_bool(table, "non-root")?.unwrap_or(false),
+                tzdata: get_bool(table, "tzdata")?.unwrap_or(false),
+                read_only: get_bool(table, "read-only")?.unwrap_or(false),
+                assets: get_list(table, "assets")?.unwrap_or_default(),
+                workdir: get_str(table, "workdir")?,
+                steps: get_steps(table)?,
+                command: get_commands(table, "build-command")?,
+                outputs: get_list(table, "build-outputs")?.unwrap_or_default(),
+                vendor: get_bool(table, "vendor")?.unwrap_or(false),
+                sccache: None,
+                optimize: get_bool(table, "optimize")?.unwrap_or(false),
+            },
+        })
+    }
+
+    /// `--project` / `--region` flags shared by every `gcloud run` call for this service.
+    pub fn target_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if let Some(project) = &self.project {
+            flags.push(format!("--project={project}"));
+        }
+        if let Some(region) = &self.region {
+            flags.push(format!("--region={region}"));
+        }
+        flags
+    }
+
+    /// Flags for `gcloud run deploy` describing the service (resources, scaling, env, ...).
+    pub fn deploy_flags(&self) -> Vec<String> {
+        let mut flags = self.target_flags();
+        if self.allow_unauthenticated {
+            flags.push("--allow-unauthenticated".to_string());
+        } else {
+            flags.push("--no-allow-unauthenticated".to_string());
+        }
+        if self.http2 {
+            flags.push("--use-http2".to_string());
+        }
+        if let Some(memory) = &self.memory {
+            flags.push(format!("--memory={memory}"));
+        }
+        if let Some(cpu) = &self.cpu {
+            flags.push(format!("--cpu={cpu}"));
+        }
+        if let Some(n) = self.min_instances {
+            flags.push(format!("--min-instances={n}"));
+        }
+        if let Some(n) = self.max_instances {
+            flags.push(format!("--max-instances={n}"));
+        }
+        if let Some(n) = self.concurrency {
+            flags.push(format!("--concurrency={n}"));
+        }
+        if let Some(timeout) = &self.timeout {
+            flags.push(format!("--timeout={timeout}"));
+        }
+        if let Some(account) = &self.service_account {
+            flags.push(format!("--service-account={account}"));
+        }
+        if !self.env.is_empty() {
+            // Use a custom delimiter so values may contain commas
+            let vars = self
+                .env
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join("@@");
+            flags.push(format!("--set-env-vars=^@@^{vars}"));
+        }
+        flags
+    }
+
+    /// Name of the env var dependents receive this service's URL in, e.g. `USERS_API_URL`.
+    pub fn url_env_var(&self) -> String {
+        let name = self
+            .service
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect::<String>();
+        format!("{name}_URL")
+    }
+
+    /// The registry images for this service are stored in. Defaults to the repository
+    /// `gcloud run deploy --source` creates.
+    pub fn registry(&self) -> Option<String> {
+        if let Some(registry) = &self.registry {
+            return Some(registry.trim_end_matches('/').to_string());
+        }
+        let (Some(project), Some(region)) = (&self.project, &self.region) else {
+            return None;
+        };
+        Some(format!(
+            "{region}-docker.pkg.dev/{}/cloud-run-source-deploy",
+            project.replace(':', "/")
+        ))
+    }
+}
+
+/// Settings shared by every service of the workspace, read from `[workspace.metadata.cloudrun]`.
+#[derive(Debug, Clone, Default)]
+pub struct WorkspaceConfig {
+    pub sccache: Option<Sccache>,
+}
+
+/// Where sccache keeps compiled artifacts, e.g.
+///
+/// ```toml
+/// [workspace.metadata.cloudrun.sccache]
+/// bucket = "acme-sccache"
+/// dir = "/var/cache/sccache"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sccache {
+    /// GCS bucket used by builds in Cloud Build.
+    pub bucket: Option<String>,
+    /// Prefix of the objects in the bucket.
+    pub prefix: Option<String>,
+    /// Directory used by local builds.
+    pub dir: Option<String>,
+}
+
+impl WorkspaceConfig {
+    pub fn load(workspace: &Workspace) -> Result<Self, Box<dyn Error>> {
+        let Some(table) = workspace.metadata.pointer("/cloudrun") else {
+            return Ok(WorkspaceConfig::default());
+        };
+        let Some(table) = table.as_object() else {
+            return Err("`workspace.metadata.cloudrun` must be a table".into());
+        };
+        let sccache = match table.get("sccache") {
+            None => None,
+            Some(Value::Object(sccache)) => {
+                let sccache = Sccache {
+                    bucket: get_str(sccache, "bucket")?,
+                    prefix: get_str(sccache, "prefix")?,
+                    dir: get_str(sccache, "dir")?,
+                };
+                if sccache.bucket.is_none() && sccache.dir.is_none() {
+                    return Err("`sccache` needs a `bucket` or a `dir`".into());
+                }
+                Some(sccache)
+            }
+            Some(_) => return Err("`sccache` must be a table".into()),
+        };
+        Ok(WorkspaceConfig { sccache })
+    }
+}
+
+/// Merge `overrides` into `table`. Nested tables (like `env`) are merged key by key,
+/// everything else is replaced.
+fn merge(table: &mut Map<String, Value>, overrides: &Map<String, Value>) {
+    for (key, value) in overrides {
+        match (table.get_mut(key), value) {
+            (Some(Value::Object(existing)), Value::Object(nested)) => merge(existing, nested),
+            _ => {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn scalar_to_string(key: &str, value: &Value) -> Result<String, Box<dyn Error>> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(format!("`{key}` must be a string, number or boolean").into()),
+    }
+}
+
+fn get_str(table: &Map<String, Value>, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    table
+        .get(key)
+        .map(|value| scalar_to_string(key, value))
+        .transpose()
+}
+
+fn get_map(table: &Map<String, Value>, key: &str) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    let mut map = BTreeMap::new();
+    if let Some(vars) = table.get(key) {
+        let Some(vars) = vars.as_object() else {
+            return Err(format!("`{key}` must be a table of strings").into());
+        };
+        for (name, value) in vars {
+            map.insert(name.clone(), scalar_to_string(name, value)?);
+        }
+    }
+    Ok(map)
+}
+
+fn get_list(table: &Map<String, Value>, key: &str) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("`{key}` must be a list of strings").into())
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()
+            .map(Some),
+        Some(_) => Err(format!("`{key}` must be a list of strings").into()),
+    }
+}
+
+/// A command or a list of commands.
+fn get_commands(table: &Map<String, Value>, key: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    match table.get(key) {
+        Some(Value::String(command)) => Ok(vec![command.clone()]),
+        _ => Ok(get_list(table, key)?.unwrap_or_default()),
+    }
+}
+
+fn get_steps(table: &Map<String, Value>) -> Result<Vec<BuildStep>, Box<dyn Error>> {
+    let Some(steps) = table.get("steps") else {
+        return Ok(Vec::new());
+    };
+    let Some(steps) = steps.as_array() else {
+        return Err("`steps` must be an array of tables".into());
+    };
+    let mut parsed = Vec::<BuildStep>::new();
+    for step in steps {
+        let Some(step) = step.as_object() else {
+            return Err("`steps` must be an array of tables".into());
+        };
+        let Some(name) = get_str(step, "name")? else {
+            return Err("Every build step needs a `name`".into());
+        };
+        let Some(image) = get_str(step, "image")? else {
+            return Err(format!("Build step '{name}' needs an `image`").into());
+        };
+        let run = get_commands(step, "run")?;
+        if run.is_empty() {
+            return Err(format!("Build step '{name}' needs commands to `run`").into());
+        }
+        if parsed.iter().any(|s| s.name == name) {
+            return Err(format!("There is more than one build step named '{name}'").into());
+        }
+        parsed.push(BuildStep {
+            name,
+            image,
+            dir: get_str(step, "dir")?,
+            run,
+            outputs: get_list(step, "outputs")?.unwrap_or_default(),
+        });
+    }
+    Ok(parsed)
+}
+
+fn get_u64(table: &Map<String, Value>, key: &str) -> Result<Option<u64>, Box<dyn Error>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("`{key}` must be a non-negative integer").into()),
+    }
+}
+
+fn get_bool(table: &Map<String, Value>, key: &str) -> Result<Option<bool>, Box<dyn Error>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_bool()
+            .map(Some)
+            .ok_or_else(|| format!("`{key}` must be a boolean").into()),
+    }
+}
@@ -0,0 +1,546 @@
+use crate::config::{BuildConfig, ServiceConfig};
+use crate::dockerfile;
+use crate::metadata::{package_dir, package_name, Workspace};
+use crate::output::human_bytes;
+use crate::ContextArgs;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+/// Files in the workspace root a member needs besides its own sources and path dependencies.
+pub const ROOT_FILES: [&str; 6] = [
+    "Cargo.toml",
+    "Cargo.lock",
+    "rust-toolchain",
+    "rust-toolchain.toml",
+    ".cargo/config",
+    ".cargo/config.toml",
+];
+
+/// Files of a staged context that `stage` or `vendor` rewrite. They are copied from the
+/// workspace, a hard link would let the rewrite change the workspace's own file.
+const REWRITTEN_FILES: [&str; 6] = [
+    "Cargo.toml",
+    "Cargo.lock",
+    "Dockerfile",
+    ".gcloudignore",
+    ".cargo/config",
+    ".cargo/config.toml",
+];
+
+/// Files gcloud uploads from `dir`, i.e. everything not excluded by its `.gcloudignore`.
+/// Paths are relative to `dir` and sorted.
+pub fn upload_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    upload_files_in(dir, &[dir])
+}
+
+/// Like [`upload_files`], but only looking inside `subdirs` of `dir`.
+fn upload_files_in(dir: &Path, subdirs: &[&Path]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let ignore = Arc::new(gcloudignore(dir)?);
+    let mut files = Vec::new();
+    for subdir in subdirs {
+        let ignore = Arc::clone(&ignore);
+        let walker = WalkBuilder::new(subdir)
+            .standard_filters(false)
+            .follow_links(true)
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                !ignore.matched(entry.path(), is_dir).is_ignore()
+            })
+            .build();
+        for entry in walker {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                files.push(entry.path().strip_prefix(dir)?.to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Directories of `pkg` and its transitive path dependencies, split into those inside the
+/// workspace root and those outside of it.
+fn source_dirs<'a>(workspace: &'a Workspace, pkg: &'a Value) -> (Vec<&'a Path>, Vec<(&'a Value, &'a Path)>) {
+    let mut inside = vec![package_dir(pkg).unwrap_or(&workspace.root)];
+    let mut outside = Vec::new();
+    for dep in workspace.transitive_path_dependencies(pkg) {
+        let Some(dir) = package_dir(dep) else { continue };
+        if dir.starts_with(&workspace.root) {
+            inside.push(dir);
+        } else {
+            outside.push((dep, dir));
+        }
+    }
+    (inside, outside)
+}
+
+/// The files [`stage`] puts into the build context of `pkg`, relative to the workspace root:
+/// the whole upload set for a package at the root, otherwise the package, its path
+/// dependencies inside the workspace and the root files.
+pub fn staged_files(workspace: &Workspace, pkg: &Value) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let root_dir = &workspace.root;
+    if package_dir(pkg).unwrap_or(root_dir) == root_dir {
+        return upload_files(root_dir);
+    }
+    let (dirs, _) = source_dirs(workspace, pkg);
+    let mut files = upload_files_in(root_dir, &dirs)?;
+    files.extend(ROOT_FILES.iter().map(PathBuf::from).filter(|f| root_dir.join(f).is_file()));
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Every file the build of `pkg` reads: the staged files and those of path dependencies
+/// outside the workspace. Paths are absolute.
+pub fn package_files(workspace: &Workspace, pkg: &Value) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = staged_files(workspace, pkg)?
+        .into_iter()
+        .map(|file| workspace.root.join(file))
+        .collect::<Vec<_>>();
+    for (_, dir) in source_dirs(workspace, pkg).1 {
+        files.extend(upload_files(dir)?.into_iter().map(|file| dir.join(file)));
+    }
+    Ok(files)
+}
+
+/// Build a private build context for `pkg` in `target/cloudrun/<service>`, with the package's
+/// own `Dockerfile` (or a generated one) at its root. This lets several services be uploaded
+/// at the same time.
+///
+/// A member of a larger workspace only gets its own directory, its transitive path
+/// dependencies, `Cargo.lock` and the workspace manifest with `members` narrowed down to
+/// those. A package at the workspace root gets the whole upload set.
+/// Files are hard-linked rather than copied where possible.
+pub fn stage(workspace: &Workspace, pkg: &Value, config: &ServiceConfig) -> Result<PathBuf, Box<dyn Error>> {
+    let root_dir = &workspace.root;
+    let context = root_dir.join("target").join("cloudrun").join(&config.service);
+    if context.exists() {
+        fs::remove_dir_all(&context)?;
+    }
+    fs::create_dir_all(&context)?;
+
+    let own_dir = package_dir(pkg).unwrap_or(root_dir);
+    let pruned = own_dir != root_dir;
+    if pruned {
+        let (dirs, outside) = source_dirs(workspace, pkg);
+        check_sources(root_dir, pkg, &config.build, &dirs)?;
+        for (dep, dir) in outside {
+            eprintln!(
+                "Warning: path dependency '{}' is outside the workspace at {} and is not uploaded",
+                package_name(dep),
+                dir.display()
+            );
+        }
+        narrow_members(root_dir, &context, &dirs)?;
+    }
+
+    for file in staged_files(workspace, pkg)? {
+        let to = context.join(&file);
+        if to.exists() {
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let rewritten = REWRITTEN_FILES.iter().any(|f| file == Path::new(f));
+        if rewritten || fs::hard_link(root_dir.join(&file), &to).is_err() {
+            fs::copy(root_dir.join(&file), &to)?;
+        }
+    }
+    if root_dir.join(".gcloudignore").exists() {
+        fs::copy(root_dir.join(".gcloudignore"), context.join(".gcloudignore"))?;
+    }
+
+    let dockerfile_path = context.join("Dockerfile");
+    if own_dir.join("Dockerfile").exists() {
+        // A Dockerfile next to the package's manifest is used as-is
+        fs::copy(own_dir.join("Dockerfile"), &dockerfile_path)?;
+    } else {
+        fs::write(&dockerfile_path, dockerfile::generate(root_dir, pkg, &config.build)?)?;
+    }
+
+    if pruned && context.join("Cargo.lock").exists() {
+        prune_lockfile(&context);
+    }
+    if config.build.vendor {
+        vendor(&context)?;
+    }
+    Ok(context)
+}
+
+/// Reject assets and build step directories outside `dirs`, the directories a pruned context
+/// is made of. The image could not copy them.
+fn check_sources(root_dir: &Path, pkg: &Value, build: &BuildConfig, dirs: &[&Path]) -> Result<(), Box<dyn Error>> {
+    let steps = build
+        .steps
+        .iter()
+        .map(|step| (format!("Directory of build step '{}'", step.name), step.dir.as_deref().unwrap_or(".")));
+    let assets = build.assets.iter().map(|asset| (format!("Asset '{asset}'"), asset.as_str()));
+    for (what, rel) in assets.chain(steps) {
+        // Paths leaving the workspace are rejected with the Dockerfile
+        let Some(path) = dockerfile::context_path(root_dir, pkg, rel) else { continue };
+        if !dirs.iter().any(|dir| root_dir.join(&path).starts_with(dir)) {
+            return Err(format!(
+                "{what} of '{}' is outside the package and its path dependencies, the only directories \
+                 uploaded for a workspace member",
+                package_name(pkg)
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Run `cargo vendor` for the staged context, with the credentials of the local cargo and git,
+/// and point cargo in the image at `vendor/`. Only sources end up in the context, no tokens.
+fn vendor(context: &Path) -> Result<(), Box<dyn Error>> {
+    let output = Command::new("cargo")
+        .args(["vendor", "--locked", "--versioned-dirs", "--manifest-path"])
+        .arg(context.join("Cargo.toml"))
+        .arg(context.join("vendor"))
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err("`cargo vendor` failed".into());
+    }
+    // cargo prints the source replacement config, with the absolute local path to vendor/
+    let source_config = String::from_utf8_lossy(&output.stdout)
+        .replace(&context.join("vendor").to_string_lossy().to_string(), "vendor");
+
+    let cargo_dir = context.join(".cargo");
+    let config_path = match cargo_dir.join("config").exists() {
+        true => cargo_dir.join("config"),
+        false => cargo_dir.join("config.toml"),
+    };
+    let mut config = fs::read_to_string(&config_path).unwrap_or_default();
+    if config_path.exists() {
+        // Staged files are links to the workspace, write a copy instead
+        fs::remove_file(&config_path)?;
+    }
+    config.push('\n');
+    config.push_str(&source_config);
+    fs::create_dir_all(&cargo_dir)?;
+    fs::write(&config_path, config)?;
+
+    // Upload vendor/ even if the project's .gcloudignore excludes it
+    let ignore_path = context.join(".gcloudignore");
+    let mut ignore = fs::read_to_string(&ignore_path).unwrap_or_default();
+    if ignore_path.exists() {
+        fs::remove_file(&ignore_path)?;
+    }
+    ignore.push_str("\n!/vendor/\n");
+    fs::write(&ignore_path, ignore)?;
+    Ok(())
+}
+
+/// Tables a virtual manifest may have.
+const WORKSPACE_TABLES: [&str; 5] = ["workspace", "patch", "replace", "profile", "cargo-features"];
+
+/// Write the workspace manifest to `context` with `members` listing only `dirs`. A package at
+/// the root is dropped unless it is one of `dirs`, its sources are not staged.
+fn narrow_members(root_dir: &Path, context: &Path, dirs: &[&Path]) -> Result<(), Box<dyn Error>> {
+    let mut manifest = fs::read_to_string(root_dir.join("Cargo.toml"))?.parse::<toml::Table>()?;
+    if !dirs.contains(&root_dir) {
+        // Without the package, the resolver no longer follows its edition
+        let resolver = match manifest.get("package").and_then(|p| p.get("edition")).and_then(toml::Value::as_str) {
+            Some("2021") => Some("2"),
+            Some("2024") => Some("3"),
+            _ => None,
+        };
+        manifest.retain(|key, _| WORKSPACE_TABLES.contains(&key));
+        if let (Some(resolver), Some(toml::Value::Table(ws))) = (resolver, manifest.get_mut("workspace")) {
+            ws.entry("resolver").or_insert_with(|| toml::Value::String(resolver.to_string()));
+        }
+    }
+    if let Some(toml::Value::Table(ws)) = manifest.get_mut("workspace") {
+        let members = dirs
+            .iter()
+            .filter_map(|dir| dir.strip_prefix(root_dir).ok())
+            .map(|dir| toml::Value::String(dir.to_string_lossy().replace('\\', "/")))
+            .collect();
+        ws.insert("members".to_string(), toml::Value::Array(members));
+        ws.remove("default-members");
+    }
+    fs::write(context.join("Cargo.toml"), toml::to_string(&manifest)?)?;
+    Ok(())
+}
+
+/// Let cargo drop the packages of left-out members from the copied `Cargo.lock`,
+/// so `--locked` builds of the pruned context still pass. Versions are not changed.
+fn prune_lockfile(context: &Path) {
+    let status = Command::new("cargo")
+        .args(["metadata", "--format-version=1", "--manifest-path"])
+        .arg(context.join("Cargo.toml"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if !status.is_ok_and(|s| s.success()) {
+        eprintln!("Warning: Failed to prune Cargo.lock of the build context in {}", context.display());
+    }
+}
+
+/// Parse `dir/.gcloudignore`, including files pulled in with gcloud's `#!include:<file>` directive.
+/// If there is none yet, the one [`ensure_gcloudignore`] would write is used.
+fn gcloudignore(dir: &Path) -> Result<Gitignore, Box<dyn Error>> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let path = dir.join(".gcloudignore");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => generate_gcloudignore(dir)?,
+    };
+    for line in content.lines() {
+        if let Some(include) = line.strip_prefix("#!include:") {
+            if let Some(err) = builder.add(dir.join(include.trim())) {
+                // gcloud ignores missing include files too
+                if dir.join(include.trim()).exists() {
+                    return Err(err.into());
+                }
+            }
+            continue;
+        }
+        builder.add_line(Some(path.clone()), line)?;
+    }
+    Ok(builder.build()?)
+}
+
+/// Whether `path` inside `dir` is excluded from the upload by the `.gcloudignore` of `dir`.
+pub fn is_ignored(dir: &Path, path: &Path) -> Result<bool, Box<dyn Error>> {
+    let ignore = gcloudignore(dir)?;
+    Ok(ignore.matched_path_or_any_parents(path, path.is_dir()).is_ignore())
+}
+
+/// A hash of everything that would be uploaded from `dir`: file names and contents,
+/// which includes the Dockerfile and with it the toolchain and build flags.
+pub fn source_hash(dir: &Path) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    for file in upload_files(dir)? {
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(Sha256::digest(fs::read(dir.join(&file))?));
+    }
+    Ok(short_hex(&hasher.finalize()))
+}
+
+/// A hash of arbitrary strings, e.g. deploy flags.
+pub fn strings_hash<S: AsRef<str>>(parts: &[S]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_ref().as_bytes());
+        hasher.update([0]);
+    }
+    short_hex(&hasher.finalize())
+}
+
+/// The first 128 bits as hex, short enough to be stored in a label (max 63 characters).
+fn short_hex(digest: &[u8]) -> String {
+    digest[..16].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Write a `.gcloudignore` to `root_dir` unless it already has one.
+pub fn ensure_gcloudignore(root_dir: &Path) {
+    if !root_dir.join(".gcloudignore").exists() {
+        if let Err(e) = create_gcloudignore(root_dir) {
+            let gcloudignore_path = root_dir.join(".gcloudignore");
+            eprintln!("Warning: Failed to create {}: {}", gcloudignore_path.display(), e);
+        }
+    }
+}
+
+fn create_gcloudignore(root_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let gcloudignore_content = generate_gcloudignore(root_dir)?;
+    let mut file = fs::File::create(root_dir.join(".gcloudignore"))?;
+    file.write_all(gcloudignore_content.as_bytes())?;
+    Ok(())
+}
+
+/// Rust-specific defaults followed by the rules of every `.gitignore` in the tree,
+/// rewritten to be relative to `root_dir`.
+fn generate_gcloudignore(root_dir: &Path) -> io::Result<String> {
+    let mut content = String::from(
+        r#"# Generated by cargo-cloudrun from the .gitignore files of the project.
+# Rust build artifacts
+/target/
+/debug/
+/target/**/*
+# Build directories of workspace members
+target/
+.git
+.gitignore
+.gcloudignore
+node_modules/
+"#,
+    );
+
+    // Walk with gitignore rules applied so ignored directories are not searched
+    let walker = WalkBuilder::new(root_dir)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+    let mut gitignores = walker
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name() == ".gitignore")
+        .map(|entry| entry.into_path())
+        .collect::<Vec<_>>();
+    gitignores.sort();
+
+    for gitignore in gitignores {
+        let dir = gitignore
+            .parent()
+            .and_then(|p| p.strip_prefix(root_dir).ok())
+            .unwrap_or(Path::new(""));
+        let rules = fs::read_to_string(&gitignore)?
+            .lines()
+            .filter_map(|line| rebase_rule(line, dir))
+            .collect::<Vec<_>>();
+        if rules.is_empty() {
+            continue;
+        }
+        let relative = gitignore.strip_prefix(root_dir).unwrap_or(&gitignore);
+        content.push_str(&format!("\n# From {}\n", relative.display()));
+        for rule in rules {
+            content.push_str(&rule);
+            content.push('\n');
+        }
+    }
+
+    // The image is built from these even if git ignores them, e.g. the `Cargo.lock` of a library
+    content.push_str("\n# Build inputs of the image\n");
+    for file in ROOT_FILES.iter().chain(&["Dockerfile"]) {
+        content.push_str(&format!("!/{file}\n"));
+    }
+    Ok(content)
+}
+
+/// Rewrite a rule of the `.gitignore` in `dir` (relative to the root) so it means the
+/// same thing in a `.gcloudignore` at the root. Comments and blank lines are dropped.
+fn rebase_rule(line: &str, dir: &Path) -> Option<String> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negation, pattern) = match line.strip_prefix('!') {
+        Some(pattern) => ("!", pattern),
+        None => ("", line),
+    };
+    if dir.as_os_str().is_empty() {
+        return Some(line.to_string());
+    }
+
+    let dir = dir.to_string_lossy().replace('\\', "/");
+    // A slash anywhere but at the end anchors the pattern to the .gitignore's directory
+    let anchored = pattern.trim_end_matches('/').contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    Some(if anchored {
+        format!("{negation}/{dir}/{pattern}")
+    } else {
+        format!("{negation}/{dir}/**/{pattern}")
+    })
+}
+
+/// Print every file that would be uploaded with its size, then the total.
+pub fn handle_context(args: &ContextArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = Workspace::load()?;
+    // A member is uploaded from its pruned context, see `stage`
+    let files = match workspace.find_root_package() {
+        Ok(pkg) => staged_files(&workspace, pkg)?,
+        Err(_) => upload_files(&workspace.root)?,
+    };
+
+    let mut total = 0;
+    let mut sized = Vec::with_capacity(files.len());
+    for file in files {
+        let size = fs::metadata(workspace.root.join(&file))?.len();
+        total += size;
+        sized.push((file, size));
+    }
+    if args.largest {
+        sized.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    }
+
+    for (file, size) in &sized {
+        println!("{:>10}  {}", human_bytes(*size), file.display());
+    }
+    println!("{} files, {} would be uploaded from {}", sized.len(), human_bytes(total), workspace.root.display());
+
+    if total > args.warn_size * 1024 * 1024 {
+        eprintln!(
+            "Warning: the build context is larger than {}MiB, consider adding the largest files to .gcloudignore (see --largest)",
+            args.warn_size
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebases_gitignore_rules() {
+        let cases = [
+            // (directory of the .gitignore, rule, rule in the root .gcloudignore)
+            ("", "target/", Some("target/")),
+            ("", "!keep.log", Some("!keep.log")),
+            ("web", "# comment", None),
+            ("web", "   ", None),
+            ("web", "*.log", Some("/web/**/*.log")),
+            ("web", "node_modules/", Some("/web/**/node_modules/")),
+            ("web", "/dist", Some("/web/dist")),
+            ("web", "/dist/", Some("/web/dist/")),
+            ("web", "build/out", Some("/web/build/out")),
+            ("web", "!keep.log", Some("!/web/**/keep.log")),
+            ("web", "!/dist/index.html", Some("!/web/dist/index.html")),
+            ("crates/api", "*.tmp  ", Some("/crates/api/**/*.tmp")),
+        ];
+        for (dir, rule, expected) in cases {
+            assert_eq!(
+                rebase_rule(rule, Path::new(dir)).as_deref(),
+                expected,
+                "rule {rule:?} in {dir:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn uploads_build_inputs_ignored_by_git() {
+        let dir = std::env::temp_dir().join(format!("cloudrun-gitignored-lockfile-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join(".gitignore"), "/target\nCargo.lock\n*.toml\n").unwrap();
+        for file in ["Cargo.toml", "Cargo.lock", "rust-toolchain.toml", "notes.toml", "src/main.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let files = upload_files(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let files = files.unwrap();
+        let expected = ["Cargo.lock", "Cargo.toml", "rust-toolchain.toml", "src/main.rs"];
+        assert_eq!(files, expected.map(PathBuf::from));
+    }
+
+    #[test]
+    fn narrows_root_package_out_of_members_manifest() {
+        let root = std::env::temp_dir().join(format!("cloudrun-narrow-members-{}", std::process::id()));
+        let context = root.join("target/cloudrun/svc");
+        fs::create_dir_all(&context).unwrap();
+        let manifest = "[package]\nname = \"root\"\nedition = \"2021\"\n\n[dependencies]\nserde = \"1\"\n\n\
+                        [workspace]\nmembers = [\"svc\", \"other\"]\ndefault-members = [\"other\"]\n\n\
+                        [profile.release]\nlto = true\n";
+        fs::write(root.join("Cargo.toml"), manifest).unwrap();
+        let narrowed = narrow_members(&root, &context, &[&root.join("svc")])
+            .and_then(|()| Ok(fs::read_to_string(context.join("Cargo.toml"))?.parse::<toml::Table>()?));
+        fs::remove_dir_all(&root).unwrap();
+
+        let expected = "[workspace]\nmembers = [\"svc\"]\nresolver = \"2\"\n\n[profile.release]\nlto = true\n";
+        assert_eq!(narrowed.unwrap(), expected.parse::<toml::Table>().unwrap());
+    }
+}
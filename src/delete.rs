@@ -0,0 +1,244 @@
+use crate::config::ServiceConfig;
+use crate::metadata::Workspace;
+use crate::{gcloud, DeleteArgs};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::Input;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error;
+
+/// Delete the service and, if requested, everything that only exists because of it.
+pub fn handle_delete(args: &DeleteArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = Workspace::load()?;
+    let pkg = workspace.find_root_package()?;
+    let config = ServiceConfig::load(pkg, args.environment.as_deref())?;
+    let flags = config.target_flags();
+
+    // 1. Collect everything that will be removed before touching anything
+    let service = gcloud::describe_service(&config.service, &flags)?;
+    let url = service.pointer("/status/url").and_then(Value::as_str).unwrap_or_default();
+    let urls = service_urls(&service);
+    let region = config.region.clone().or_else(|| {
+        service
+            .pointer("/metadata/labels/cloud.googleapis.com~1location")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    });
+    let project_flags = config.project.iter().map(|p| format!("--project={p}")).collect::<Vec<_>>();
+
+    let tagged = tagged_revisions(&service);
+    let triggers = if args.triggers || args.all {
+        eventarc_triggers(&config.service, region.as_deref(), &project_flags)?
+    } else {
+        Vec::new()
+    };
+    let jobs = if (args.scheduler_jobs || args.all) && !urls.is_empty() {
+        scheduler_jobs(&urls, region.as_deref(), &project_flags)?
+    } else {
+        Vec::new()
+    };
+    let (images, shared_images) = if args.images || args.all {
+        let images = revision_images(&config.service, &flags)?;
+        // e.g. promoted to another environment, deleting it would break that service's rollbacks
+        let used_elsewhere = images_used_elsewhere(pkg, &config)?;
+        images.into_iter().partition(|image| !used_elsewhere.contains(image))
+    } else {
+        (BTreeSet::new(), BTreeSet::new())
+    };
+
+    // 2. Show the plan and confirm
+    println!("This will delete Cloud Run service '{}' {url}", config.service);
+    for (tag, revision) in &tagged {
+        println!("  tagged revision  {revision} ({tag})");
+    }
+    for trigger in &triggers {
+        println!("  eventarc trigger {trigger}");
+    }
+    for job in &jobs {
+        println!("  scheduler job    {job}");
+    }
+    for image in &images {
+        println!("  image            {image}");
+    }
+    for image in &shared_images {
+        println!("Keeping image {image}, revisions of other services use it");
+    }
+
+    if !args.yes {
+        let typed: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Type '{}' to confirm", config.service))
+            .allow_empty(true)
+            .interact_text()?;
+        if typed.trim() != config.service {
+            return Err("Confirmation did not match the service name, nothing was deleted".into());
+        }
+    }
+
+    // 3. Remove whatever points at the service first, then the service, then its images
+    let location_flags = region.iter().map(|r| format!("--location={r}")).collect::<Vec<_>>();
+    for trigger in &triggers {
+        let mut cmd_args = vec!["eventarc", "triggers", "delete", trigger, "--quiet"];
+        cmd_args.extend(location_flags.iter().chain(&project_flags).map(String::as_str));
+        gcloud::run(&cmd_args)?;
+    }
+    for job in &jobs {
+        let mut cmd_args = vec!["scheduler", "jobs", "delete", job, "--quiet"];
+        cmd_args.extend(location_flags.iter().chain(&project_flags).map(String::as_str));
+        gcloud::run(&cmd_args)?;
+    }
+
+    let mut cmd_args = vec!["run", "services", "delete", &config.service, "--quiet"];
+    cmd_args.extend(flags.iter().map(String::as_str));
+    gcloud::run(&cmd_args)?;
+
+    let mut failed_images = 0;
+    for image in &images {
+        let mut cmd_args = if image.contains(".pkg.dev/") {
+            vec!["artifacts", "docker", "images", "delete", image, "--delete-tags", "--quiet"]
+        } else {
+            vec!["container", "images", "delete", image, "--force-delete-tags", "--quiet"]
+        };
+        cmd_args.extend(project_flags.iter().map(String::as_str));
+        // An image may be shared with another service or already gone, keep going
+        if let Err(err) = gcloud::run(&cmd_args) {
+            eprintln!("Warning: Failed to delete image {image}: {err}");
+            failed_images += 1;
+        }
+    }
+
+    if failed_images > 0 {
+        return Err(format!("Service deleted, but {failed_images} image(s) could not be deleted").into());
+    }
+    println!("Deleted '{}'", config.service);
+    Ok(())
+}
+
+/// `(tag, revision)` pairs of the service's traffic tags.
+fn tagged_revisions(service: &Value) -> Vec<(String, String)> {
+    service
+        .pointer("/status/traffic")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|target| {
+            let tag = target.get("tag").and_then(Value::as_str)?;
+            let revision = target.get("revisionName").and_then(Value::as_str).unwrap_or("LATEST");
+            Some((tag.to_string(), revision.to_string()))
+        })
+        .collect()
+}
+
+/// Names of the Eventarc triggers delivering to `service`.
+fn eventarc_triggers(
+    service: &str,
+    region: Option<&str>,
+    project_flags: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut cmd_args = vec!["eventarc".to_string(), "triggers".to_string(), "list".to_string()];
+    if let Some(region) = region {
+        cmd_args.push(format!("--location={region}"));
+    }
+    cmd_args.extend(project_flags.iter().cloned());
+    let triggers = gcloud::json(&cmd_args)?;
+
+    Ok(triggers
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|t| t.pointer("/destination/cloudRun/service").and_then(Value::as_str) == Some(service))
+        .filter_map(|t| t.get("name").and_then(Value::as_str))
+        .map(|name| name.rsplit('/').next().unwrap_or(name).to_string())
+        .collect())
+}
+
+/// Every URL the service is reachable at: `status.url` and those of the
+/// `run.googleapis.com/urls` annotation, which include the deterministic one.
+fn service_urls(service: &Value) -> Vec<String> {
+    let mut urls = service
+        .pointer("/metadata/annotations/run.googleapis.com~1urls")
+        .and_then(Value::as_str)
+        .and_then(|urls| serde_json::from_str::<Vec<String>>(urls).ok())
+        .unwrap_or_default();
+    if let Some(url) = service.pointer("/status/url").and_then(Value::as_str) {
+        urls.push(url.to_string());
+    }
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/// Names of the Cloud Scheduler jobs that call one of the service's URLs.
+fn scheduler_jobs(
+    urls: &[String],
+    region: Option<&str>,
+    project_flags: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut cmd_args = vec!["scheduler".to_string(), "jobs".to_string(), "list".to_string()];
+    if let Some(region) = region {
+        cmd_args.push(format!("--location={region}"));
+    }
+    cmd_args.extend(project_flags.iter().cloned());
+    let jobs = gcloud::json(&cmd_args)?;
+
+    Ok(jobs
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|job| {
+            job.pointer("/httpTarget/uri")
+                .and_then(Value::as_str)
+                .is_some_and(|uri| urls.iter().any(|url| uri.starts_with(url.as_str())))
+        })
+        .filter_map(|job| job.get("name").and_then(Value::as_str))
+        .map(|name| name.rsplit('/').next().unwrap_or(name).to_string())
+        .collect())
+}
+
+/// Image digests of every revision of the service.
+fn revision_images(service: &str, flags: &[String]) -> Result<BTreeSet<String>, Box<dyn Error>> {
+    let mut cmd_args = vec!["run", "revisions", "list", "--service", service];
+    cmd_args.extend(flags.iter().map(String::as_str));
+    let revisions = gcloud::json(&cmd_args)?;
+
+    Ok(revisions
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|r| r.pointer("/status/imageDigest").and_then(Value::as_str))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Image digests of the revisions of every other service in the project and region of each
+/// environment of `pkg`, i.e. wherever `promote` may have deployed the service's images.
+fn images_used_elsewhere(pkg: &Value, config: &ServiceConfig) -> Result<BTreeSet<String>, Box<dyn Error>> {
+    let mut targets = BTreeSet::from([config.target_flags(), ServiceConfig::load(pkg, None)?.target_flags()]);
+    for environment in ServiceConfig::environments(pkg) {
+        targets.insert(ServiceConfig::load(pkg, Some(&environment))?.target_flags());
+    }
+
+    let own_flags = config.target_flags();
+    let mut images = BTreeSet::new();
+    for flags in targets {
+        let mut cmd_args = vec!["run", "revisions", "list"];
+        cmd_args.extend(flags.iter().map(String::as_str));
+        let revisions = gcloud::json(&cmd_args)?;
+        for revision in revisions.as_array().map(Vec::as_slice).unwrap_or_default() {
+            let service = revision
+                .pointer("/metadata/labels/serving.knative.dev~1service")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            if service == config.service && flags == own_flags {
+                continue;
+            }
+            if let Some(digest) = revision.pointer("/status/imageDigest").and_then(Value::as_str) {
+                images.insert(digest.to_string());
+            }
+        }
+    }
+    Ok(images)
+}
@@ -0,0 +1,685 @@
+use crate::buildlog;
+use crate::cloudbuild;
+use crate::config::{CloudBuildConfig, ServiceConfig, WorkspaceConfig};
+use crate::context;
+use crate::dockerfile;
+use crate::metadata::{self, Workspace};
+use crate::output::{self, human_bytes, Prefix};
+use crate::services::{self, Service};
+use crate::{gcloud, git, table, DeployArgs};
+use serde_json::Value;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+/// Deploy the resolved package, or every deployable workspace member with `--workspace`.
+pub fn handle_deploy(args: &DeployArgs) -> Result<(), Box<dyn Error>> {
+    // 1. Find the workspace root and the root package name
+    let workspace = Workspace::load_locked()?;
+    if args.workspace {
+        return deploy_workspace(&workspace, args);
+    }
+
+    let pkg = workspace.find_root_package()?;
+    let workspace_config = WorkspaceConfig::load(&workspace)?;
+    let mut config = ServiceConfig::load(pkg, args.environment.as_deref())?;
+    apply_build_flags(&mut config, &workspace_config, args);
+
+    let deps = inject_deployed_dependencies(&workspace, args.environment.as_deref(), &mut config)?;
+
+    // Vendored dependencies must not end up in the project itself
+    if metadata::package_dir(pkg) == Some(workspace.root.as_path()) && !config.build.vendor {
+        deploy_in_place(&workspace.root, pkg, &config, args)?;
+    } else {
+        // A member of a larger workspace only uploads what it needs
+        context::ensure_gcloudignore(&workspace.root);
+        let context = context::stage(&workspace, pkg, &config)?;
+        run_deploy(&context, &workspace.root, pkg, &config, args, None)?;
+    }
+    for dep in grant_invoker(&config, &deps.iter().collect::<Vec<_>>())? {
+        println!("Allowed '{}' to invoke '{dep}'", config.service);
+    }
+    Ok(())
+}
+
+/// Inject the URLs of the services `config` depends on, as they are currently deployed in
+/// `environment`, and return their configs. Only the listed services are loaded.
+pub fn inject_deployed_dependencies(
+    workspace: &Workspace,
+    environment: Option<&str>,
+    config: &mut ServiceConfig,
+) -> Result<Vec<ServiceConfig>, Box<dyn Error>> {
+    let mut deps = Vec::new();
+    for name in &config.depends_on {
+        let Some(dep) = services::load_named(workspace, environment, name)? else {
+            return Err(format!("'{}' depends on unknown service '{name}'", config.service).into());
+        };
+        deps.push(dep.config);
+    }
+    for dep in &deps {
+        let url = deployed_url(dep)?;
+        inject_url(config, dep, &url);
+    }
+    Ok(deps)
+}
+
+/// Deploy a single package straight from the workspace root.
+fn deploy_in_place(
+    root_dir: &Path,
+    pkg: &Value,
+    config: &ServiceConfig,
+    args: &DeployArgs,
+) -> Result<(), Box<dyn Error>> {
+    // 2. Change directory to the root package directory
+    env::set_current_dir(root_dir)
+        .map_err(|err| format!("Failed to change directory to {}: {err}", root_dir.display()))?;
+
+    let mut delete_dockerfile = false;
+    if File::open(root_dir.join("Dockerfile")).is_err() {
+        // 3. Build the Dockerfile content, referencing the found package name
+        let dockerfile_content = dockerfile::generate(root_dir, pkg, &config.build)?;
+
+        // 4. Write the Dockerfile in the crate root
+        let dockerfile_path = root_dir.join("Dockerfile");
+        fs::write(&dockerfile_path, &dockerfile_content)
+            .map_err(|err| format!("Failed to write Dockerfile: {err}"))?;
+        delete_dockerfile = true;
+    }
+
+    context::ensure_gcloudignore(root_dir);
+
+    // let previous_image = Command::new("gcloud")
+    //     .args([
+    //         "run",
+    //         "services",
+    //         "describe",
+    //         &root_package_name,
+    //         "--format=value(image)"
+    //     ])
+    //     .output()
+    //     .ok()
+    //     .and_then(|output| {
+    //         if output.status.success() {
+    //             String::from_utf8_lossy(&output.stdout)
+    //                 .trim()
+    //                 .to_string()
+    //                 .into()
+    //         } else {
+    //             None
+    //         }
+    //     })
+    //     .filter(|s| !s.is_empty())
+    //     .map(|s| format!("--cache-from={}", s))
+    //     .unwrap_or_default();
+
+    // if !previous_image.is_empty() {
+    //     cmd_args.push(previous_image);
+    // }
+
+    let result = run_deploy(root_dir, root_dir, pkg, config, args, None);
+    maybe_delete_dockerfile(&mut delete_dockerfile);
+    result.map(|_| ())
+}
+
+/// Where a service of a workspace deploy is at.
+enum State {
+    Pending,
+    Running,
+    Done(Result<Option<String>, String>),
+}
+
+/// Deploy every deployable member concurrently, each from its own staged build context.
+/// Services only start once everything they `depends-on` has been deployed.
+fn deploy_workspace(workspace: &Workspace, args: &DeployArgs) -> Result<(), Box<dyn Error>> {
+    let workspace_config = WorkspaceConfig::load(workspace)?;
+    let (mut all, mut selected) = services::load_excluding(workspace, args.environment.as_deref(), &args.exclude)?;
+    for service in &mut all {
+        apply_build_flags(&mut service.config, &workspace_config, args);
+    }
+    let deps = services::dependencies(&all)?;
+
+    if !selected.contains(&true) {
+        return Err("No services to deploy".into());
+    }
+
+    if let Some(since) = &args.changed_since {
+        let changed = git::changed_files(&workspace.root, since)?;
+        for (service, selected) in all.iter().zip(selected.iter_mut()) {
+            if *selected && !is_affected(workspace, service.pkg, &changed) {
+                println!("Skipping {}: unchanged since {since}", service.config.service);
+                *selected = false;
+            }
+        }
+        if !selected.contains(&true) {
+            println!("No services changed since {since}");
+            return Ok(());
+        }
+    }
+
+    // Staged contexts are linked from the root, so it needs its .gcloudignore first
+    context::ensure_gcloudignore(&workspace.root);
+
+    let count = selected.iter().filter(|s| **s).count();
+    let width = all.iter().map(|s| s.config.service.len()).max().unwrap_or(0);
+    let jobs = args.jobs.max(1).min(count);
+    println!("Deploying {count} services, {jobs} at a time");
+
+    let states = Mutex::new(
+        selected
+            .iter()
+            .map(|&s| if s { State::Pending } else { State::Done(Ok(None)) })
+            .collect::<Vec<_>>(),
+    );
+    let changed = Condvar::new();
+    let elapsed = Mutex::new(vec![Duration::ZERO; all.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                while let Some(index) = next_ready(&states, &changed, &deps, &selected, &all) {
+                    let service = &all[index];
+                    let prefix = Prefix::new(&service.config.service, index, width);
+                    let started = Instant::now();
+
+                    let result = inject_dependency_urls(&service.config, &deps[index], &all, &states, &selected)
+                        .and_then(|config| {
+                            let url = deploy_staged(workspace, service.pkg, &config, args, &prefix)?;
+                            let dep_configs = deps[index].iter().map(|&d| &all[d].config).collect::<Vec<_>>();
+                            for dep in grant_invoker(&config, &dep_configs)? {
+                                println!("{}", prefix.line(&format!("Allowed to invoke '{dep}'")));
+                            }
+                            Ok(url)
+                        })
+                        .map_err(|err| err.to_string());
+                    if let Err(err) = &result {
+                        eprintln!("{}", prefix.line(err));
+                    }
+
+                    elapsed.lock().unwrap()[index] = started.elapsed();
+                    states.lock().unwrap()[index] = State::Done(result);
+                    changed.notify_all();
+                }
+            });
+        }
+    });
+
+    let states = states.into_inner().unwrap();
+    let elapsed = elapsed.into_inner().unwrap();
+
+    println!();
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for (index, state) in states.iter().enumerate() {
+        if !selected[index] {
+            continue;
+        }
+        let (result, detail) = match state {
+            State::Done(Ok(url)) => ("ok", url.clone().unwrap_or_default()),
+            State::Done(Err(err)) => {
+                failed += 1;
+                ("FAILED", err.clone())
+            }
+            State::Pending | State::Running => unreachable!("all services finish before the scope ends"),
+        };
+        rows.push(vec![
+            all[index].config.service.clone(),
+            result.to_string(),
+            format_duration(elapsed[index]),
+            detail,
+        ]);
+    }
+    table::print(&["SERVICE", "RESULT", "TIME", "URL / ERROR"], &rows);
+
+    if failed > 0 {
+        return Err(format!("{failed} of {count} services failed to deploy").into());
+    }
+    Ok(())
+}
+
+/// Block until a pending service has all its selected dependencies deployed and claim it.
+/// Services whose dependencies failed are failed as well. Returns `None` once nothing is pending.
+fn next_ready(
+    states: &Mutex<Vec<State>>,
+    changed: &Condvar,
+    deps: &[Vec<usize>],
+    selected: &[bool],
+    all: &[Service],
+) -> Option<usize> {
+    let mut states = states.lock().unwrap();
+    loop {
+        let mut pending = false;
+        let mut progressed = false;
+        for index in 0..states.len() {
+            if !matches!(states[index], State::Pending) {
+                continue;
+            }
+            let in_run = deps[index].iter().filter(|&&d| selected[d]);
+            if let Some(&failed) = in_run.clone().find(|&&d| matches!(states[d], State::Done(Err(_)))) {
+                let reason = format!("not deployed, dependency '{}' failed", all[failed].config.service);
+                states[index] = State::Done(Err(reason));
+                progressed = true;
+                continue;
+            }
+            if in_run.clone().all(|&d| matches!(states[d], State::Done(Ok(_)))) {
+                states[index] = State::Running;
+                return Some(index);
+            }
+            pending = true;
+        }
+        if progressed {
+            changed.notify_all();
+            continue;
+        }
+        if !pending {
+            return None;
+        }
+        states = changed.wait(states).unwrap();
+    }
+}
+
+/// A copy of `config` with the URLs of its dependencies injected, using the URL just
+/// deployed in this run where there is one and the currently deployed one otherwise.
+fn inject_dependency_urls(
+    config: &ServiceConfig,
+    deps: &[usize],
+    all: &[Service],
+    states: &Mutex<Vec<State>>,
+    selected: &[bool],
+) -> Result<ServiceConfig, Box<dyn Error>> {
+    let mut config = config.clone();
+    for &dep in deps {
+        let deployed = match &states.lock().unwrap()[dep] {
+            State::Done(Ok(url)) if selected[dep] => url.clone(),
+            _ => None,
+        };
+        let url = match deployed {
+            Some(url) => url,
+            None => deployed_url(&all[dep].config)?,
+        };
+        inject_url(&mut config, &all[dep].config, &url);
+    }
+    Ok(config)
+}
+
+/// Add the workspace-wide build settings and let build flags given to `deploy` override the metadata.
+fn apply_build_flags(config: &mut ServiceConfig, workspace_config: &WorkspaceConfig, args: &DeployArgs) {
+    let build = &mut config.build;
+    build.sccache = workspace_config.sccache.clone();
+    if !args.features.is_empty() {
+        build.features = args.features.clone();
+    }
+    if args.no_default_features {
+        build.no_default_features = true;
+    }
+    if let Some(profile) = &args.profile {
+        build.profile = Some(profile.clone());
+    }
+    if let Some(rustflags) = &args.rustflags {
+        build.rustflags = Some(rustflags.clone());
+    }
+    build.env.extend(args.build_env.iter().cloned());
+    build.args.extend(args.build_args.iter().cloned());
+    if args.vendor {
+        build.vendor = true;
+    }
+    if args.optimize {
+        build.optimize = true;
+    }
+    if args.cloud_build && config.cloud_build.is_none() {
+        config.cloud_build = Some(CloudBuildConfig::default());
+    }
+}
+
+/// The URL of a service as it is currently deployed.
+fn deployed_url(config: &ServiceConfig) -> Result<String, Box<dyn Error>> {
+    let service = gcloud::describe_service(&config.service, &config.target_flags())
+        .map_err(|err| format!("Could not look up dependency '{}': {err}", config.service))?;
+    service
+        .pointer("/status/url")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| format!("Dependency '{}' has no URL yet", config.service).into())
+}
+
+/// Set `<DEP>_URL` on `config`, unless its env already sets that variable explicitly.
+fn inject_url(config: &mut ServiceConfig, dep: &ServiceConfig, url: &str) {
+    config
+        .env
+        .entry(dep.url_env_var())
+        .or_insert_with(|| url.to_string());
+}
+
+/// Allow the runtime service account of `config` to invoke every dependency that does
+/// not allow unauthenticated access. Returns the services access was granted to.
+pub fn grant_invoker(config: &ServiceConfig, deps: &[&ServiceConfig]) -> Result<Vec<String>, Box<dyn Error>> {
+    let private = deps.iter().filter(|d| !d.allow_unauthenticated).collect::<Vec<_>>();
+    if private.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let account = match &config.service_account {
+        Some(account) => account.clone(),
+        None => gcloud::describe_service(&config.service, &config.target_flags())?
+            .pointer("/spec/template/spec/serviceAccountName")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| format!("Could not determine the service account of '{}'", config.service))?,
+    };
+
+    let mut granted = Vec::new();
+    for dep in private {
+        let mut cmd_args = vec![
+            "run".to_string(),
+            "services".to_string(),
+            "add-iam-policy-binding".to_string(),
+            dep.service.clone(),
+            format!("--member=serviceAccount:{account}"),
+            "--role=roles/run.invoker".to_string(),
+        ];
+        cmd_args.extend(dep.target_flags());
+        gcloud::json(&cmd_args)?;
+        granted.push(dep.service.clone());
+    }
+    Ok(granted)
+}
+
+/// Whether any of `changed` belongs to `pkg` or one of its transitive path dependencies.
+/// Changes to the root files every staged context gets, like the lockfile or the toolchain,
+/// affect every package.
+fn is_affected(workspace: &Workspace, pkg: &Value, changed: &[PathBuf]) -> bool {
+    let mut ids = workspace
+        .transitive_path_dependencies(pkg)
+        .into_iter()
+        .filter_map(|dep| dep.get("id").and_then(Value::as_str))
+        .collect::<Vec<_>>();
+    ids.extend(pkg.get("id").and_then(Value::as_str));
+
+    changed.iter().any(|file| {
+        if context::ROOT_FILES.iter().any(|root_file| *file == workspace.root.join(root_file)) {
+            return true;
+        }
+        workspace
+            .owning_package(file)
+            .and_then(|owner| owner.get("id").and_then(Value::as_str))
+            .is_some_and(|id| ids.contains(&id))
+    })
+}
+
+/// Deploy one service from `target/cloudrun/<service>`, prefixing its output.
+/// Returns the service URL reported by gcloud.
+fn deploy_staged(
+    workspace: &Workspace,
+    pkg: &Value,
+    config: &ServiceConfig,
+    args: &DeployArgs,
+    prefix: &Prefix,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let context = context::stage(workspace, pkg, config)?;
+    run_deploy(&context, &workspace.root, pkg, config, args, Some(prefix))
+}
+
+/// Revision labels recording what a revision was built from and deployed with.
+const SOURCE_HASH_LABEL: &str = "source-hash";
+const CONFIG_HASH_LABEL: &str = "config-hash";
+
+/// What a deploy has to do, given what is currently deployed.
+enum Plan {
+    /// The live revision already runs this source with this config.
+    Skip { revision: String, url: Option<String> },
+    /// Only the config changed, so the live image is redeployed without building.
+    Redeploy { image: String },
+    /// Upload the source and build it.
+    Build,
+}
+
+/// Deploy the build context in `context` unless the live revision already matches it.
+/// Output is prefixed when a `prefix` is given. Returns the service URL if gcloud reported one.
+fn run_deploy(
+    context: &Path,
+    root_dir: &Path,
+    pkg: &Value,
+    config: &ServiceConfig,
+    args: &DeployArgs,
+    prefix: Option<&Prefix>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let log = |message: &str| match prefix {
+        Some(prefix) => println!("{}", prefix.line(message)),
+        None => println!("{message}"),
+    };
+
+    let source_hash = context::source_hash(context)?;
+    let config_hash = context::strings_hash(&[config.deploy_flags(), args.extra_args.clone()].concat());
+    let mut build_log = None;
+
+    let source = match plan(config, &source_hash, &config_hash, args.force) {
+        Plan::Skip { revision, url } => {
+            log(&format!("Skipping deploy, {revision} already runs this source and config (use --force to deploy anyway)"));
+            return Ok(url);
+        }
+        Plan::Redeploy { image } => {
+            log(&format!("Source unchanged, redeploying {image} with the new config"));
+            vec![format!("--image={image}")]
+        }
+        Plan::Build if config.cloud_build.is_some() => {
+            let mut tags = vec![source_hash.clone()];
+            tags.extend(git::head_sha(root_dir));
+            let build = cloudbuild::submit(context, root_dir, pkg, config, &tags, prefix)?;
+            build_log = Some(build.lines.join("\n"));
+            vec![format!("--image={}", build.image)]
+        }
+        Plan::Build => vec!["--source".to_string(), ".".to_string()],
+    };
+    let building = build_log.is_some() || source[0] == "--source";
+    let sccache = building && config.build.sccache.as_ref().is_some_and(|s| s.bucket.is_some());
+    let optimize = building && config.build.optimize;
+    let previous_image = if optimize { serving_image(config) } else { None };
+
+    let mut cmd_args = vec!["run".to_string(), "deploy".to_string(), config.service.clone()];
+    cmd_args.extend(source);
+    cmd_args.extend(config.deploy_flags());
+    let mut labels = vec![
+        format!("{SOURCE_HASH_LABEL}={source_hash}"),
+        format!("{CONFIG_HASH_LABEL}={config_hash}"),
+    ];
+    if let Some(sha) = git::head_sha(root_dir) {
+        labels.push(format!("{}={sha}", git::SHA_LABEL));
+    }
+    cmd_args.push(format!("--update-labels={}", labels.join(",")));
+
+    // Add any additional arguments from DeployArgs
+    if !args.extra_args.is_empty() {
+        cmd_args.push("--".to_string());
+        cmd_args.extend(args.extra_args.iter().cloned());
+    }
+
+    let mut command = Command::new("gcloud");
+    command.args(&cmd_args).current_dir(context);
+    // The build log is found through the link in the output
+    let needs_log = sccache || optimize;
+    let (status, lines) = match prefix {
+        Some(prefix) => output::run_captured(&mut command, Some(prefix)),
+        None if needs_log => output::run_captured(&mut command, None),
+        None => command.status().map(|status| (status, Vec::new())),
+    }
+    .map_err(|err| format!("Failed to spawn gcloud process: {err}"))?;
+    if !status.success() {
+        return Err(format!("gcloud run deploy failed with status: {:?}", status.code()).into());
+    }
+
+    if needs_log && build_log.is_none() {
+        build_log = buildlog::fetch(config, &lines);
+    }
+    if sccache {
+        match build_log.as_deref().and_then(buildlog::sccache_stats) {
+            Some(stats) => log(&format!("sccache: {stats}")),
+            None => log("sccache: no stats found in the build log"),
+        }
+    }
+    if optimize {
+        report_sizes(config, build_log.as_deref(), previous_image.as_deref(), log);
+    }
+
+    Ok(lines
+        .iter()
+        .find_map(|line| line.split_once("Service URL:"))
+        .map(|(_, url)| url.trim().to_string()))
+}
+
+/// The image the serving revision of the service runs, by digest.
+fn serving_image(config: &ServiceConfig) -> Option<String> {
+    let flags = config.target_flags();
+    let service = gcloud::describe_service(&config.service, &flags).ok()?;
+    let revision = gcloud::describe_revision(&gcloud::serving_revision(&service)?, &flags).ok()?;
+    revision
+        .pointer("/status/imageDigest")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+/// Print the binary size from the build log and the image size, compared to `previous`.
+fn report_sizes(config: &ServiceConfig, log: Option<&str>, previous: Option<&str>, print: impl Fn(&str)) {
+    if let Some(size) = log.and_then(buildlog::binary_size) {
+        print(&format!("Binary size: {}", human_bytes(size)));
+    }
+    let Some(size) = serving_image(config).and_then(|image| gcloud::image_size(&image)) else {
+        print("Image size: unknown");
+        return;
+    };
+    match previous.and_then(gcloud::image_size) {
+        Some(before) => {
+            let delta = size as i64 - before as i64;
+            let sign = if delta < 0 { "-" } else { "+" };
+            print(&format!(
+                "Image size: {} ({sign}{} compared to the previous image)",
+                human_bytes(size),
+                human_bytes(delta.unsigned_abs())
+            ));
+        }
+        None => print(&format!("Image size: {}", human_bytes(size))),
+    }
+}
+
+/// Compare the hashes with the labels of the revision currently serving traffic.
+fn plan(config: &ServiceConfig, source_hash: &str, config_hash: &str, force: bool) -> Plan {
+    if force {
+        return Plan::Build;
+    }
+    let flags = config.target_flags();
+    let Ok(service) = gcloud::describe_service(&config.service, &flags) else {
+        return Plan::Build;
+    };
+    let Some(revision) = gcloud::serving_revision(&service) else {
+        return Plan::Build;
+    };
+    let Ok(revision_json) = gcloud::describe_revision(&revision, &flags) else {
+        return Plan::Build;
+    };
+    let label = |name: &str| {
+        revision_json
+            .pointer(&format!("/metadata/labels/{name}"))
+            .and_then(Value::as_str)
+    };
+
+    if label(SOURCE_HASH_LABEL) != Some(source_hash) {
+        return Plan::Build;
+    }
+    if label(CONFIG_HASH_LABEL) == Some(config_hash) {
+        let url = service.pointer("/status/url").and_then(Value::as_str).map(str::to_string);
+        return Plan::Skip { revision, url };
+    }
+    match revision_json.pointer("/status/imageDigest").and_then(Value::as_str) {
+        Some(image) => Plan::Redeploy { image: image.to_string() },
+        None => Plan::Build,
+    }
+}
+
+fn maybe_delete_dockerfile(delete_dockerfile: &mut bool) {
+    if *delete_dockerfile {
+        if let Err(e) = fs::remove_file("Dockerfile") {
+            eprintln!("Warning: Failed to delete temporary Dockerfile: {}", e);
+        }
+    }
+}
+
+/// e.g. `2m05s`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}m{:02}s", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{test_packages, test_services};
+
+    /// `a` depends on `b`, which depends on `c`.
+    fn chain() -> Vec<Value> {
+        test_packages(&[("a", &["b"]), ("b", &["c"]), ("c", &[])])
+    }
+
+    fn pending(count: usize) -> Mutex<Vec<State>> {
+        Mutex::new((0..count).map(|_| State::Pending).collect())
+    }
+
+    fn finish(states: &Mutex<Vec<State>>, index: usize, result: Result<Option<String>, String>) {
+        states.lock().unwrap()[index] = State::Done(result);
+    }
+
+    #[test]
+    fn deploys_dependencies_first() {
+        let packages = chain();
+        let all = test_services(&packages);
+        let deps = services::dependencies(&all).unwrap();
+        let selected = vec![true; 3];
+        let states = pending(3);
+        let changed = Condvar::new();
+
+        assert_eq!(next_ready(&states, &changed, &deps, &selected, &all), Some(2));
+        finish(&states, 2, Ok(None));
+        assert_eq!(next_ready(&states, &changed, &deps, &selected, &all), Some(1));
+        finish(&states, 1, Ok(None));
+        assert_eq!(next_ready(&states, &changed, &deps, &selected, &all), Some(0));
+        finish(&states, 0, Ok(None));
+        assert_eq!(next_ready(&states, &changed, &deps, &selected, &all), None);
+    }
+
+    #[test]
+    fn fails_dependents_of_failed_services() {
+        let packages = chain();
+        let all = test_services(&packages);
+        let deps = services::dependencies(&all).unwrap();
+        let selected = vec![true; 3];
+        let states = pending(3);
+        let changed = Condvar::new();
+
+        assert_eq!(next_ready(&states, &changed, &deps, &selected, &all), Some(2));
+        finish(&states, 2, Err("build failed".to_string()));
+        assert_eq!(next_ready(&states, &changed, &deps, &selected, &all), None);
+
+        let states = states.into_inner().unwrap();
+        let error = |index: usize| match &states[index] {
+            State::Done(Err(err)) => err.clone(),
+            _ => panic!("service {index} did not fail"),
+        };
+        assert_eq!(error(1), "not deployed, dependency 'c' failed");
+        assert_eq!(error(0), "not deployed, dependency 'b' failed");
+    }
+
+    #[test]
+    fn ignores_dependencies_outside_the_run() {
+        let packages = chain();
+        let all = test_services(&packages);
+        let deps = services::dependencies(&all).unwrap();
+        // `c` is excluded, so `b` does not wait for it
+        let selected = vec![true, true, false];
+        let states = pending(3);
+        finish(&states, 2, Ok(None));
+        let changed = Condvar::new();
+
+        assert_eq!(next_ready(&states, &changed, &deps, &selected, &all), Some(1));
+    }
+}
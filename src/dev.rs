@@ -0,0 +1,190 @@
+use crate::config::WorkspaceConfig;
+use crate::metadata::{package_dir, Workspace};
+use crate::output::{self, Prefix};
+use crate::services::{self, Service};
+use crate::{local, table, DevArgs};
+use std::error::Error;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often crashed services are looked for.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A service that ran at least this long before crashing is restarted without backing off.
+const STABLE_AFTER: Duration = Duration::from_secs(10);
+
+/// Upper limit of the delay between restarts of a service that keeps crashing.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// One service started by `dev`, restarted whenever it exits.
+struct Process {
+    prefix: Prefix,
+    binary: PathBuf,
+    env: Vec<(String, String)>,
+    port: u16,
+    child: Option<Child>,
+    started: Instant,
+    listening: bool,
+    restart_delay: Duration,
+    restart_at: Option<Instant>,
+}
+
+impl Process {
+    fn spawn(&mut self) {
+        let child = Command::new(&self.binary)
+            .envs(self.env.iter().cloned())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                eprintln!("{}", self.prefix.line(&format!("Failed to start: {err}")));
+                self.schedule_restart();
+                return;
+            }
+        };
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let prefix = self.prefix.clone();
+        thread::spawn(move || output::print_lines(stdout, Some(&prefix), false));
+        let prefix = self.prefix.clone();
+        thread::spawn(move || output::print_lines(stderr, Some(&prefix), true));
+
+        self.child = Some(child);
+        self.started = Instant::now();
+        self.listening = false;
+        self.restart_at = None;
+    }
+
+    /// Restart after a delay that grows while the service keeps crashing soon after starting.
+    fn schedule_restart(&mut self) {
+        self.restart_delay = if self.started.elapsed() < STABLE_AFTER {
+            (self.restart_delay * 2).min(MAX_RESTART_DELAY)
+        } else {
+            POLL_INTERVAL
+        };
+        self.restart_at = Some(Instant::now() + self.restart_delay);
+    }
+
+    /// Print when the service starts listening and restart it when it exited.
+    fn supervise(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(child) = &mut self.child {
+            if let Some(status) = child.try_wait()? {
+                self.child = None;
+                self.schedule_restart();
+                let delay = self.restart_delay.as_secs_f64();
+                println!("{}", self.prefix.line(&format!("Exited with {status}, restarting in {delay:.1}s")));
+            } else if !self.listening && TcpStream::connect(("127.0.0.1", self.port)).is_ok() {
+                self.listening = true;
+                println!("{}", self.prefix.line(&format!("Listening on http://127.0.0.1:{}", self.port)));
+            }
+        } else if self.restart_at.is_some_and(|at| at <= Instant::now()) {
+            println!("{}", self.prefix.line("Restarting"));
+            self.spawn();
+        }
+        Ok(())
+    }
+}
+
+/// Build every deployable service of the workspace and run them side by side, each on its own
+/// port and with its dependencies' local URLs, until interrupted.
+pub fn handle_dev(args: &DevArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = Workspace::load()?;
+    let sccache = WorkspaceConfig::load(&workspace)?.sccache;
+    let (mut all, selected) = services::load_excluding(&workspace, args.environment.as_deref(), &args.exclude)?;
+    let deps = services::dependencies(&all)?;
+
+    let mut ports = Vec::with_capacity(all.len());
+    for index in 0..all.len() {
+        ports.push(match args.base_port {
+            Some(base) => u16::try_from(index)
+                .ok()
+                .and_then(|index| base.checked_add(index))
+                .ok_or_else(|| format!("--base-port {base} is too high for {} services, ports end at 65535", all.len()))?,
+            None => local::free_port()?,
+        });
+    }
+    for service in &mut all {
+        service.config.build.sccache = sccache.clone();
+    }
+
+    let mut binaries = Vec::new();
+    for (index, service) in all.iter().enumerate().filter(|(index, _)| selected[*index]) {
+        println!("Building '{}'", service.config.service);
+        binaries.push((index, local::build(&workspace, service.pkg, &service.config.build)?));
+    }
+
+    let width = all.iter().map(|s| s.config.service.len()).max().unwrap_or(0);
+    let mut processes = Vec::new();
+    let mut rows = Vec::new();
+    for (index, binary) in binaries {
+        let service = &all[index];
+        let env = service_env(&all, &deps[index], &selected, &ports, index)?;
+        rows.push(vec![service.config.service.clone(), format!("http://127.0.0.1:{}", ports[index])]);
+        processes.push(Process {
+            prefix: Prefix::new(&service.config.service, index, width),
+            binary,
+            env,
+            port: ports[index],
+            child: None,
+            started: Instant::now(),
+            listening: false,
+            restart_delay: POLL_INTERVAL,
+            restart_at: None,
+        });
+    }
+    println!();
+    table::print(&["SERVICE", "URL"], &rows);
+    println!();
+
+    for process in &mut processes {
+        process.spawn();
+    }
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        for process in &mut processes {
+            process.supervise()?;
+        }
+    }
+}
+
+/// Cloud Run's env for the service on its port, the local URL of every dependency under the
+/// name production uses, and the package's `.env` file if it has one.
+fn service_env(
+    all: &[Service],
+    deps: &[usize],
+    selected: &[bool],
+    ports: &[u16],
+    index: usize,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let service = &all[index];
+    let mut config = service.config.clone();
+    for &dep in deps {
+        let dep_config = &all[dep].config;
+        if !selected[dep] {
+            eprintln!(
+                "Warning: '{}' depends on '{}', which is excluded, {} is not set",
+                config.service,
+                dep_config.service,
+                dep_config.url_env_var()
+            );
+            continue;
+        }
+        // Like in production, an env var set in the metadata wins
+        config
+            .env
+            .entry(dep_config.url_env_var())
+            .or_insert_with(|| format!("http://127.0.0.1:{}", ports[dep]));
+    }
+
+    let mut env = local::cloud_run_env(&config, ports[index]);
+    if let Some(env_file) = package_dir(service.pkg).map(|dir| dir.join(".env")).filter(|path| path.exists()) {
+        env.extend(local::read_env_file(&env_file)?);
+    }
+    Ok(env)
+}
@@ -0,0 +1,587 @@
+use crate::config::{BuildConfig, BuildStep, Runtime};
+use crate::buildlog::BINARY_SIZE_MARKER;
+use crate::context;
+use crate::metadata::{package_dir, package_name};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The Rust toolchain a package is built with.
+#[derive(Debug, Default)]
+pub struct Toolchain {
+    /// e.g. `1.82`, `1.82.0`, `stable` or `nightly-2024-11-01`
+    channel: Option<String>,
+    components: Vec<String>,
+    targets: Vec<String>,
+    /// Whether the channel comes from a `rust-toolchain(.toml)` file, which rustup then
+    /// picks up inside the image.
+    from_file: bool,
+}
+
+impl Toolchain {
+    /// The toolchain pinned by `rust-toolchain.toml` (or the legacy `rust-toolchain`) in the
+    /// workspace root, falling back to `package.rust-version`.
+    pub fn resolve(root_dir: &Path, pkg: &Value) -> Result<Self, Box<dyn Error>> {
+        for name in ["rust-toolchain.toml", "rust-toolchain"] {
+            let path = root_dir.join(name);
+            if path.is_file() {
+                let content = fs::read_to_string(&path)?;
+                return Self::parse(&content).map_err(|err| format!("Invalid {}: {err}", path.display()).into());
+            }
+        }
+        Ok(Toolchain {
+            channel: pkg.get("rust_version").and_then(Value::as_str).map(str::to_string),
+            ..Default::default()
+        })
+    }
+
+    fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let trimmed = content.trim();
+        // The legacy file may contain nothing but the channel
+        if !trimmed.is_empty() && !trimmed.contains(['=', '[', '\n']) {
+            return Ok(Toolchain {
+                channel: Some(trimmed.to_string()),
+                from_file: true,
+                ..Default::default()
+            });
+        }
+
+        let table = trimmed.parse::<toml::Table>()?;
+        let Some(toolchain) = table.get("toolchain").and_then(toml::Value::as_table) else {
+            return Err("missing [toolchain] table".into());
+        };
+        if toolchain.contains_key("path") {
+            return Err("a toolchain `path` cannot be used inside the image".into());
+        }
+        let list = |key: &str| -> Result<Vec<String>, Box<dyn Error>> {
+            match toolchain.get(key) {
+                None => Ok(Vec::new()),
+                Some(toml::Value::Array(items)) => items
+                    .iter()
+                    .map(|item| {
+                        item.as_str()
+                            .map(str::to_string)
+                            .ok_or_else(|| format!("`{key}` must be a list of strings").into())
+                    })
+                    .collect(),
+                Some(_) => Err(format!("`{key}` must be a list of strings").into()),
+            }
+        };
+        Ok(Toolchain {
+            channel: toolchain.get("channel").and_then(toml::Value::as_str).map(str::to_string),
+            components: list("components")?,
+            targets: list("targets")?,
+            from_file: true,
+        })
+    }
+
+    /// The official `rust` image closest to the channel. Images are pinned to bookworm so the
+    /// binary links against the same glibc as the distroless runtime image.
+    fn builder_image(&self) -> String {
+        match self.channel.as_deref().filter(|c| is_version(c)) {
+            Some(version) => format!("rust:{version}-{}", self.debian_release()),
+            None => "rust:1-bookworm".to_string(),
+        }
+    }
+
+    /// Debian release of the builder image. Rust images before 1.70 predate the bookworm variants.
+    fn debian_release(&self) -> &'static str {
+        let minor = self
+            .channel
+            .as_deref()
+            .filter(|c| is_version(c))
+            .and_then(|version| version.split('.').nth(1))
+            .and_then(|minor| minor.parse::<u32>().ok());
+        match minor {
+            Some(minor) if minor < 70 => "bullseye",
+            _ => "bookworm",
+        }
+    }
+
+    /// Dockerfile instructions that install the channel, components and targets
+    /// the builder image does not come with.
+    fn install_instructions(&self) -> String {
+        if !self.from_file {
+            // Without a toolchain file the image's default toolchain is used as it is
+            return match self.targets.is_empty() {
+                true => String::new(),
+                false => format!("RUN rustup target add {}\n", self.targets.join(" ")),
+            };
+        }
+        // A version channel like `1.82` names a different toolchain than the image's `1.82.0`,
+        // so always install what rustup will look for when it reads the toolchain file
+        let channel = self.channel.as_deref().unwrap_or("stable");
+        let mut install = format!("RUN rustup toolchain install {channel} --profile minimal");
+        for component in &self.components {
+            install.push_str(&format!(" --component {component}"));
+        }
+        for target in &self.targets {
+            install.push_str(&format!(" --target {target}"));
+        }
+        install.push('\n');
+        install
+    }
+}
+
+/// `1`, `1.82` or `1.82.0`, as opposed to `stable`, `beta` or `nightly-...`.
+fn is_version(channel: &str) -> bool {
+    channel.split('.').count() <= 3 && channel.split('.').all(|part| !part.is_empty() && part.parse::<u32>().is_ok())
+}
+
+/// A `RUN` instruction installing Debian `packages`, or nothing if there are none.
+fn apt_install(packages: &[String]) -> String {
+    if packages.is_empty() {
+        return String::new();
+    }
+    format!(
+        "RUN apt-get update \\\n    && apt-get install -y --no-install-recommends {} \\\n    && rm -rf /var/lib/apt/lists/*\n",
+        packages.join(" ")
+    )
+}
+
+/// Whether the build produces a binary that runs without a libc in the image.
+fn is_static(build: &BuildConfig) -> bool {
+    build.target.as_deref().is_some_and(|t| t.contains("musl"))
+        || build.rustflags.as_deref().is_some_and(|f| f.contains("+crt-static"))
+}
+
+/// The runtime base the options ask for, rejecting combinations that cannot work.
+fn runtime(build: &BuildConfig) -> Result<Runtime, Box<dyn Error>> {
+    let runtime = match build.runtime {
+        Some(runtime) => runtime,
+        // Distroless images have no package manager
+        None if !build.runtime_packages.is_empty() => Runtime::DebianSlim,
+        None => Runtime::DistrolessCc,
+    };
+    if !build.runtime_packages.is_empty() && runtime != Runtime::DebianSlim {
+        return Err(format!(
+            "`runtime-packages` need a package manager, use `runtime = \"debian-slim\"` instead of \"{}\"",
+            runtime.name()
+        )
+        .into());
+    }
+    if matches!(runtime, Runtime::Scratch | Runtime::DistrolessStatic) && !is_static(build) {
+        return Err(format!(
+            "The binary is linked against glibc, which \"{}\" does not have. Build for a musl target \
+             (e.g. `target = \"x86_64-unknown-linux-musl\"`) or use \"distroless-cc\"",
+            runtime.name()
+        )
+        .into());
+    }
+    if build.read_only {
+        return Err(
+            "Cloud Run has no read-only root filesystem, so `read-only` cannot be enforced. Remove it, \
+             with `non-root = true` the service already cannot write to the files of the image"
+                .into(),
+        );
+    }
+    Ok(runtime)
+}
+
+/// The runtime stage up to the binary: base image, packages, certificates, time zones and user.
+fn runtime_stage(runtime: Runtime, toolchain: &Toolchain, build: &BuildConfig) -> String {
+    // UID of the `nonroot` user of the distroless images, reused for the others
+    const NON_ROOT: &str = "65532:65532";
+    let tag = if build.non_root { ":nonroot" } else { "" };
+    let mut stage = String::new();
+    match runtime {
+        Runtime::DistrolessCc => stage.push_str(&format!("FROM gcr.io/distroless/cc-debian12{tag}\n")),
+        Runtime::DistrolessStatic => stage.push_str(&format!("FROM gcr.io/distroless/static-debian12{tag}\n")),
+        Runtime::Scratch => {
+            stage.push_str("FROM scratch\n");
+            // Distroless comes with CA certificates and time zones, scratch has nothing
+            stage.push_str("COPY --from=build-env /etc/ssl/certs/ca-certificates.crt /etc/ssl/certs/\n");
+            if build.tzdata {
+                stage.push_str("COPY --from=build-env /usr/share/zoneinfo /usr/share/zoneinfo\n");
+            }
+            if build.non_root {
+                stage.push_str(&format!("USER {NON_ROOT}\n"));
+            }
+        }
+        Runtime::DebianSlim => {
+            stage.push_str(&format!("FROM debian:{}-slim\n", toolchain.debian_release()));
+            // Distroless comes with CA certificates, keep TLS working after the switch
+            let mut packages = vec!["ca-certificates".to_string()];
+            if build.tzdata {
+                packages.push("tzdata".to_string());
+            }
+            packages.extend(build.runtime_packages.iter().filter(|p| !packages.contains(p)).cloned().collect::<Vec<_>>());
+            stage.push_str(&apt_install(&packages));
+            if build.non_root {
+                stage.push_str(&format!("USER {NON_ROOT}\n"));
+            }
+        }
+    }
+    stage
+}
+
+/// `rel`, a path relative to the package, as a path relative to the root of the build context.
+/// `None` if it leaves the workspace.
+pub fn context_path(root_dir: &Path, pkg: &Value, rel: &str) -> Option<String> {
+    let dir = package_dir(pkg).unwrap_or(root_dir).strip_prefix(root_dir).ok()?;
+    let mut path = PathBuf::new();
+    for component in dir.join(rel.trim_end_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if path.pop() => {}
+            _ => return None,
+        }
+    }
+    Some(path.to_string_lossy().replace('\\', "/"))
+}
+
+/// `COPY` instructions for the assets, from the build context into `workdir`.
+fn copy_assets(root_dir: &Path, pkg: &Value, build: &BuildConfig, workdir: &str) -> Result<String, Box<dyn Error>> {
+    let mut instructions = String::new();
+    for asset in &build.assets {
+        let asset = asset.trim_end_matches('/');
+        let Some(source) = context_path(root_dir, pkg, asset) else {
+            return Err(format!("Asset '{asset}' of '{}' is outside the workspace", package_name(pkg)).into());
+        };
+        let path = root_dir.join(&source);
+        if !path.exists() {
+            return Err(format!("Asset '{asset}' of '{}' does not exist", package_name(pkg)).into());
+        }
+        if context::is_ignored(root_dir, &path)? {
+            return Err(format!("Asset '{asset}' is excluded from the upload by .gcloudignore").into());
+        }
+        instructions.push_str(&format!("COPY {source} {}/{asset}\n", workdir.trim_end_matches('/')));
+    }
+    Ok(instructions)
+}
+
+/// Name of the Dockerfile stage of a build step.
+fn step_stage(step: &BuildStep) -> String {
+    let name = step
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect::<String>();
+    format!("step-{name}")
+}
+
+/// Outputs of a build step as (path in the build context, path relative to the package).
+fn step_outputs(root_dir: &Path, pkg: &Value, step: &BuildStep) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let dir = step.dir.as_deref().unwrap_or(".");
+    step.outputs
+        .iter()
+        .map(|output| {
+            let rel = Path::new(dir).join(output.trim_end_matches('/'));
+            let rel = rel.strip_prefix(".").unwrap_or(&rel).to_string_lossy().replace('\\', "/");
+            match context_path(root_dir, pkg, &rel) {
+                Some(source) => Ok((source, rel)),
+                None => Err(format!("Output '{output}' of build step '{}' is outside the workspace", step.name).into()),
+            }
+        })
+        .collect()
+}
+
+/// A stage per build step, each working on a copy of the build context.
+fn step_stages(root_dir: &Path, pkg: &Value, build: &BuildConfig) -> Result<String, Box<dyn Error>> {
+    let mut stages = String::new();
+    for step in &build.steps {
+        let dir = step.dir.as_deref().unwrap_or(".");
+        let Some(dir) = context_path(root_dir, pkg, dir) else {
+            return Err(format!("Directory of build step '{}' is outside the workspace", step.name).into());
+        };
+        stages.push_str(&format!("FROM {} as {}\n", step.image, step_stage(step)));
+        stages.push_str("WORKDIR /app\nCOPY . /app\n");
+        if !dir.is_empty() {
+            stages.push_str(&format!("WORKDIR /app/{dir}\n"));
+        }
+        for command in &step.run {
+            stages.push_str(&format!("RUN {command}\n"));
+        }
+        stages.push('\n');
+    }
+    Ok(stages)
+}
+
+/// `cargo build` invocation for the package with the configured profile and features.
+fn build_command(package: &str, build: &BuildConfig) -> String {
+    let mut command = format!("cargo build --locked --package {package}");
+    if build.vendor {
+        // Everything is in vendor/, nothing should be fetched
+        command.push_str(" --offline");
+    }
+    if let Some(target) = &build.target {
+        command.push_str(&format!(" --target {target}"));
+    }
+    match &build.profile {
+        Some(profile) => command.push_str(&format!(" --profile {profile}")),
+        None => command.push_str(" --release"),
+    }
+    if build.no_default_features {
+        command.push_str(" --no-default-features");
+    }
+    if !build.features.is_empty() {
+        command.push_str(&format!(" --features {}", build.features.join(",")));
+    }
+    command
+}
+
+/// sccache release installed in the builder image.
+const SCCACHE_VERSION: &str = "0.10.0";
+
+/// Instructions installing sccache and pointing cargo and sccache at the bucket.
+/// Builds without a bucket (local ones) get the cache from the environment instead.
+fn sccache_setup(build: &BuildConfig) -> String {
+    let Some(bucket) = build.sccache.as_ref().and_then(|s| s.bucket.as_ref()) else {
+        return String::new();
+    };
+    let release = format!("sccache-v{SCCACHE_VERSION}-$(uname -m)-unknown-linux-musl");
+    let mut instructions = format!(
+        "RUN curl -fsSL https://github.com/mozilla/sccache/releases/download/v{SCCACHE_VERSION}/{release}.tar.gz \\\n    \
+         | tar -xz -C /usr/local/bin --strip-components=1 {release}/sccache\n"
+    );
+    instructions.push_str("ENV RUSTC_WRAPPER=sccache CARGO_INCREMENTAL=0\n");
+    instructions.push_str(&format!("ENV SCCACHE_GCS_BUCKET={bucket:?} SCCACHE_GCS_RW_MODE=READ_WRITE\n"));
+    if let Some(prefix) = build.sccache.as_ref().and_then(|s| s.prefix.as_ref()) {
+        instructions.push_str(&format!("ENV SCCACHE_GCS_KEY_PREFIX={prefix:?}\n"));
+    }
+    instructions
+}
+
+/// `ARG` and `ENV` instructions of the build stage.
+fn build_environment(build: &BuildConfig) -> String {
+    let mut instructions = String::new();
+    for (name, default) in &build.args {
+        instructions.push_str(&format!("ARG {name}={default:?}\n"));
+    }
+    if let Some(rustflags) = &build.rustflags {
+        instructions.push_str(&format!("ENV RUSTFLAGS={rustflags:?}\n"));
+    }
+    let profile_env = build.profile_env();
+    if !profile_env.is_empty() {
+        let vars = profile_env.iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>();
+        instructions.push_str(&format!("ENV {}\n", vars.join(" ")));
+    }
+    for (name, value) in &build.env {
+        instructions.push_str(&format!("ENV {name}={value:?}\n"));
+    }
+    instructions
+}
+
+/// The Dockerfile used when the project does not bring its own.
+pub fn generate(root_dir: &Path, pkg: &Value, build: &BuildConfig) -> Result<String, Box<dyn Error>> {
+    let runtime = runtime(build)?;
+    let mut toolchain = Toolchain::resolve(root_dir, pkg)?;
+    let binary = package_name(pkg);
+
+    let mut build_packages = build.build_packages.clone();
+    let mut artifacts = format!("/app/target/{}", build.profile_dir());
+    if let Some(target) = &build.target {
+        if !toolchain.targets.contains(target) {
+            toolchain.targets.push(target.clone());
+        }
+        if target.contains("musl") && !build_packages.iter().any(|p| p == "musl-tools") {
+            // C dependencies need a musl toolchain as well
+            build_packages.push("musl-tools".to_string());
+        }
+        artifacts = format!("/app/target/{target}/{}", build.profile_dir());
+    }
+    if runtime == Runtime::Scratch && build.tzdata {
+        build_packages.push("tzdata".to_string());
+    }
+    let workdir = build.workdir.as_deref().unwrap_or("/");
+    let mut files = copy_assets(root_dir, pkg, build, workdir)?;
+    let entrypoint = format!("{}/{binary}", workdir.trim_end_matches('/'));
+
+    // Step outputs are available to the cargo build (e.g. to embed them) and shipped next to the binary
+    let mut step_outputs_in_build = String::new();
+    for step in &build.steps {
+        let stage = step_stage(step);
+        for (source, rel) in step_outputs(root_dir, pkg, step)? {
+            step_outputs_in_build.push_str(&format!("COPY --from={stage} /app/{source} /app/{source}\n"));
+            files.push_str(&format!("COPY --from={stage} /app/{source} {}/{rel}\n", workdir.trim_end_matches('/')));
+        }
+    }
+    for output in &build.outputs {
+        // The build runs in the workspace root, where cargo's target directory is
+        let output = output.trim_end_matches('/');
+        if !Path::new(output).components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("Build output '{output}' of '{binary}' is outside the workspace").into());
+        }
+        files.push_str(&format!("COPY --from=build-env /app/{output} {}/{output}\n", workdir.trim_end_matches('/')));
+    }
+    if workdir != "/" {
+        files.push_str(&format!("WORKDIR {workdir}\n"));
+    }
+    let mut commands = match build.command.is_empty() {
+        true => vec![build_command(binary, build)],
+        false => build.command.clone(),
+    };
+    if build.sccache.as_ref().is_some_and(|s| s.bucket.is_some()) {
+        // The stats live in the sccache server, which does not outlive the RUN instruction
+        if let Some(last) = commands.last_mut() {
+            last.push_str(" && sccache --show-stats");
+        }
+    }
+    if build.optimize {
+        if let Some(last) = commands.last_mut() {
+            last.push_str(&format!(
+                " && echo \"{BINARY_SIZE_MARKER} $(stat -c %s {artifacts}/{binary})\""
+            ));
+        }
+    }
+    let build_commands = commands.iter().map(|c| format!("RUN {c}\n")).collect::<String>();
+
+    Ok(format!(
+        r#"
+{steps}# https://hub.docker.com/_/rust
+FROM {image} as build-env
+WORKDIR /app
+{packages}{install}{sccache}{environment}COPY . /app
+{step_outputs_in_build}{build_commands}
+{runtime}ENV PORT 8080
+{files}COPY --from=build-env {artifacts}/{binary} {entrypoint}
+ENTRYPOINT ["{entrypoint}"]
+"#,
+        image = toolchain.builder_image(),
+        packages = apt_install(&build_packages),
+        install = toolchain.install_instructions(),
+        sccache = sccache_setup(build),
+        environment = build_environment(build),
+        steps = step_stages(root_dir, pkg, build)?,
+        runtime = runtime_stage(runtime, &toolchain, build),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(channel: &str) -> Toolchain {
+        Toolchain { channel: Some(channel.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn parses_legacy_toolchain_file() {
+        let toolchain = Toolchain::parse("nightly-2024-11-01\n").unwrap();
+        assert_eq!(toolchain.channel.as_deref(), Some("nightly-2024-11-01"));
+        assert!(toolchain.components.is_empty());
+        assert!(toolchain.from_file);
+    }
+
+    #[test]
+    fn parses_toolchain_table() {
+        let toolchain = Toolchain::parse(
+            r#"
+            [toolchain]
+            channel = "1.82"
+            components = ["rustfmt", "clippy"]
+            targets = ["x86_64-unknown-linux-musl"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(toolchain.channel.as_deref(), Some("1.82"));
+        assert_eq!(toolchain.components, ["rustfmt", "clippy"]);
+        assert_eq!(toolchain.targets, ["x86_64-unknown-linux-musl"]);
+        assert!(toolchain.from_file);
+    }
+
+    #[test]
+    fn rejects_invalid_toolchain_files() {
+        let path = Toolchain::parse("[toolchain]\npath = \"/opt/rust\"").unwrap_err();
+        assert_eq!(path.to_string(), "a toolchain `path` cannot be used inside the image");
+        let components = Toolchain::parse("[toolchain]\ncomponents = \"rustfmt\"").unwrap_err();
+        assert_eq!(components.to_string(), "`components` must be a list of strings");
+        let table = Toolchain::parse("channel = \"stable\"").unwrap_err();
+        assert_eq!(table.to_string(), "missing [toolchain] table");
+    }
+
+    #[test]
+    fn picks_builder_image_for_channel() {
+        let cases = [
+            ("1.82", "rust:1.82-bookworm"),
+            ("1.82.0", "rust:1.82.0-bookworm"),
+            ("1.70", "rust:1.70-bookworm"),
+            ("1.69.0", "rust:1.69.0-bullseye"),
+            ("stable", "rust:1-bookworm"),
+            ("nightly-2024-11-01", "rust:1-bookworm"),
+        ];
+        for (name, image) in cases {
+            assert_eq!(channel(name).builder_image(), image, "channel {name}");
+        }
+        assert_eq!(Toolchain::default().builder_image(), "rust:1-bookworm");
+    }
+
+    #[test]
+    fn uses_bullseye_before_1_70() {
+        assert_eq!(channel("1.69").debian_release(), "bullseye");
+        assert_eq!(channel("1.56.1").debian_release(), "bullseye");
+        assert_eq!(channel("1.70.0").debian_release(), "bookworm");
+        assert_eq!(channel("1").debian_release(), "bookworm");
+        assert_eq!(channel("nightly-2020-01-01").debian_release(), "bookworm");
+    }
+
+    #[test]
+    fn tells_versions_from_channel_names() {
+        for version in ["1", "1.82", "1.82.0"] {
+            assert!(is_version(version), "{version}");
+        }
+        for name in ["stable", "beta", "nightly", "nightly-2024-11-01", "1.82.0.1", "1..0", "1.82-beta"] {
+            assert!(!is_version(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_runtimes_that_cannot_work() {
+        let glibc_scratch = BuildConfig { runtime: Some(Runtime::Scratch), ..Default::default() };
+        let glibc_static = BuildConfig { runtime: Some(Runtime::DistrolessStatic), ..Default::default() };
+        let packages_on_distroless = BuildConfig {
+            runtime: Some(Runtime::DistrolessCc),
+            runtime_packages: vec!["libpq5".to_string()],
+            ..Default::default()
+        };
+        let read_only = BuildConfig { read_only: true, non_root: true, ..Default::default() };
+        for build in [glibc_scratch, glibc_static, packages_on_distroless, read_only] {
+            assert!(runtime(&build).is_err(), "{build:?}");
+        }
+    }
+
+    #[test]
+    fn accepts_static_binaries_on_empty_runtimes() {
+        let musl = BuildConfig {
+            runtime: Some(Runtime::Scratch),
+            target: Some("x86_64-unknown-linux-musl".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(runtime(&musl).unwrap(), Runtime::Scratch);
+        let crt_static = BuildConfig {
+            runtime: Some(Runtime::DistrolessStatic),
+            rustflags: Some("-C target-feature=+crt-static".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(runtime(&crt_static).unwrap(), Runtime::DistrolessStatic);
+    }
+
+    #[test]
+    fn defaults_runtime_to_what_the_packages_need() {
+        assert_eq!(runtime(&BuildConfig::default()).unwrap(), Runtime::DistrolessCc);
+        let packages = BuildConfig { runtime_packages: vec!["libpq5".to_string()], ..Default::default() };
+        assert_eq!(runtime(&packages).unwrap(), Runtime::DebianSlim);
+        let non_root = BuildConfig { non_root: true, ..Default::default() };
+        assert_eq!(runtime(&non_root).unwrap(), Runtime::DistrolessCc);
+    }
+
+    #[test]
+    fn resolves_paths_relative_to_the_package() {
+        let pkg = serde_json::json!({ "name": "a", "manifest_path": "/ws/services/a/Cargo.toml" });
+        let root = Path::new("/ws");
+        let cases = [
+            ("static/", Some("services/a/static")),
+            ("./templates", Some("services/a/templates")),
+            (".", Some("services/a")),
+            ("../shared/static", Some("services/shared/static")),
+            ("../../assets", Some("assets")),
+            ("../../../etc", None),
+            ("/etc", None),
+        ];
+        for (rel, path) in cases {
+            assert_eq!(context_path(root, &pkg, rel).as_deref(), path, "{rel}");
+        }
+    }
+}
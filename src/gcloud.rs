@@ -0,0 +1,113 @@
+use serde_json::Value;
+use std::error::Error;
+use std::process::{Command, Stdio};
+
+/// Run `gcloud <args> --format=json` and parse its output.
+/// On failure the error carries gcloud's own error message.
+pub fn json<S: AsRef<str>>(args: &[S]) -> Result<Value, Box<dyn Error>> {
+    let mut args = args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    args.push("--format=json");
+    Ok(serde_json::from_str(&text(&args)?)?)
+}
+
+/// Run `gcloud <args>` and return what it printed to stdout.
+/// On failure the error carries gcloud's own error message.
+pub fn text<S: AsRef<str>>(args: &[S]) -> Result<String, Box<dyn Error>> {
+    let args = args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    let output = Command::new("gcloud")
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("Failed to spawn gcloud process: {err}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr
+            .lines()
+            .find(|line| line.starts_with("ERROR:"))
+            .or_else(|| stderr.lines().rev().find(|line| !line.trim().is_empty()))
+            .unwrap_or_default()
+            .trim();
+        return Err(format!("`gcloud {}` failed: {message}", args.join(" ")).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Run `gcloud <args>` with inherited stdio, failing if it exits unsuccessfully.
+pub fn run<S: AsRef<str>>(args: &[S]) -> Result<(), Box<dyn Error>> {
+    let args = args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    let status = Command::new("gcloud")
+        .args(&args)
+        .status()
+        .map_err(|err| format!("Failed to spawn gcloud process: {err}"))?;
+
+    if !status.success() {
+        return Err(format!("`gcloud {}` failed with status: {:?}", args.join(" "), status.code()).into());
+    }
+    Ok(())
+}
+
+/// Describe a Cloud Run service. `target_flags` are the `--project` / `--region` flags.
+pub fn describe_service(service: &str, target_flags: &[String]) -> Result<Value, Box<dyn Error>> {
+    let mut args = vec!["run", "services", "describe", service];
+    args.extend(target_flags.iter().map(String::as_str));
+    json(&args)
+}
+
+/// Describe a single Cloud Run revision.
+pub fn describe_revision(revision: &str, target_flags: &[String]) -> Result<Value, Box<dyn Error>> {
+    let mut args = vec!["run", "revisions", "describe", revision];
+    args.extend(target_flags.iter().map(String::as_str));
+    json(&args)
+}
+
+/// The revision receiving the largest share of traffic of a described service.
+pub fn serving_revision(service: &Value) -> Option<String> {
+    let latest_ready = service
+        .pointer("/status/latestReadyRevisionName")
+        .and_then(Value::as_str);
+
+    service
+        .pointer("/status/traffic")
+        .and_then(Value::as_array)?
+        .iter()
+        .filter(|t| t.get("percent").and_then(Value::as_u64).unwrap_or(0) > 0)
+        .max_by_key(|t| t.get("percent").and_then(Value::as_u64).unwrap_or(0))
+        .and_then(|t| {
+            t.get("revisionName")
+                .and_then(Value::as_str)
+                .or(latest_ready)
+        })
+        .map(str::to_string)
+}
+
+/// Whether the `Ready` condition of a described service or revision is `True`,
+/// `False` or `Unknown` (still deploying), along with its message.
+pub fn ready_condition(resource: &Value) -> (String, String) {
+    let condition = resource
+        .pointer("/status/conditions")
+        .and_then(Value::as_array)
+        .and_then(|conditions| {
+            conditions
+                .iter()
+                .find(|c| c.get("type").and_then(Value::as_str) == Some("Ready"))
+        });
+    let Some(condition) = condition else {
+        return ("Unknown".to_string(), String::new());
+    };
+    let status = condition.get("status").and_then(Value::as_str).unwrap_or("Unknown");
+    let message = condition.get("message").and_then(Value::as_str).unwrap_or_default();
+    (status.to_string(), message.to_string())
+}
+
+/// Compressed size of an Artifact Registry image given as `<name>@sha256:<digest>`.
+pub fn image_size(image: &str) -> Option<u64> {
+    let (name, digest) = image.split_once('@')?;
+    let filter = format!("--filter=version={digest}");
+    let images = json(&["artifacts", "docker", "images", "list", name, filter.as_str()]).ok()?;
+    let size = images.as_array()?.first()?.pointer("/metadata/imageSizeBytes")?;
+    match size {
+        Value::String(size) => size.parse().ok(),
+        size => size.as_u64(),
+    }
+}
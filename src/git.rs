@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Revision label recording the git commit a deploy was built from.
+pub const SHA_LABEL: &str = "git-sha";
+
+/// The commit checked out in `dir`, suffixed with `-dirty` if there are uncommitted changes.
+/// Returns `None` outside of a git repository.
+pub fn head_sha(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let sha = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let dirty = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| !output.stdout.is_empty());
+
+    Some(if dirty { format!("{sha}-dirty") } else { sha })
+}
+
+/// Files changed between the merge base of `since` and `HEAD` and the working tree,
+/// including untracked files. Paths are absolute.
+pub fn changed_files(dir: &Path, since: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let toplevel = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim());
+    let base = git(dir, &["merge-base", since, "HEAD"])?;
+    let changed = git(dir, &["diff", "--name-only", base.trim()])?;
+    let untracked = git(&toplevel, &["ls-files", "--others", "--exclude-standard", "--full-name"])?;
+
+    Ok(changed
+        .lines()
+        .chain(untracked.lines())
+        .filter(|line| !line.is_empty())
+        .map(|line| toplevel.join(line))
+        .collect())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git").args(args).current_dir(dir).output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,271 @@
+use crate::config::{BuildConfig, ServiceConfig};
+use crate::context;
+use crate::metadata::{package_name, Workspace};
+use serde_json::Value;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Build the binary of `pkg` on this machine with the same features, profile and flags the
+/// generated Dockerfile uses. Returns the path of the executable.
+pub fn build(workspace: &Workspace, pkg: &Value, build: &BuildConfig) -> Result<PathBuf, Box<dyn Error>> {
+    let binary = package_name(pkg);
+    let mut command = Command::new("cargo");
+    command
+        .args(["build", "--message-format=json-render-diagnostics", "--package", binary])
+        .current_dir(&workspace.root)
+        .stdout(Stdio::piped());
+    match &build.profile {
+        Some(profile) => command.args(["--profile", profile]),
+        None => command.arg("--release"),
+    };
+    if build.no_default_features {
+        command.arg("--no-default-features");
+    }
+    if !build.features.is_empty() {
+        command.args(["--features", &build.features.join(",")]);
+    }
+    if let Some(rustflags) = &build.rustflags {
+        command.env("RUSTFLAGS", rustflags);
+    }
+    command.envs(build.profile_env());
+    command.envs(&build.env);
+    if let Some(dir) = build.sccache.as_ref().and_then(|s| s.dir.as_ref()) {
+        if Command::new("sccache").arg("--version").output().is_ok_and(|o| o.status.success()) {
+            command.env("RUSTC_WRAPPER", "sccache").env("SCCACHE_DIR", dir);
+        } else {
+            eprintln!("Warning: sccache is not installed, building without the cache in {dir}");
+        }
+    }
+
+    let mut child = command.spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut executable = None;
+    for line in BufReader::new(stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Value>(&line?) else { continue };
+        let is_binary = message.get("reason").and_then(Value::as_str) == Some("compiler-artifact")
+            && message.pointer("/target/name").and_then(Value::as_str) == Some(binary);
+        if let Some(path) = message.get("executable").and_then(Value::as_str).filter(|_| is_binary) {
+            executable = Some(PathBuf::from(path));
+        }
+    }
+    if !child.wait()?.success() {
+        return Err(format!("Failed to build '{binary}'").into());
+    }
+    executable.ok_or_else(|| format!("`cargo build` produced no binary named '{binary}'").into())
+}
+
+/// The env Cloud Run gives a container, plus the service's env vars.
+pub fn cloud_run_env(config: &ServiceConfig, port: u16) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("PORT".to_string(), port.to_string()),
+        ("K_SERVICE".to_string(), config.service.clone()),
+        ("K_REVISION".to_string(), format!("{}-local", config.service)),
+        ("K_CONFIGURATION".to_string(), config.service.clone()),
+    ];
+    env.extend(config.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+    env
+}
+
+/// Read `KEY=VALUE` lines of a `.env` file. Blank lines, `#` comments and a leading `export`
+/// are skipped, and values may be wrapped in single or double quotes.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+    let mut vars = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("{}:{}: expected KEY=VALUE", path.display(), number + 1).into());
+        };
+        let value = value.trim();
+        let unquoted = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+        vars.push((key.trim().to_string(), unquoted.to_string()));
+    }
+    Ok(vars)
+}
+
+/// A port nothing is listening on right now.
+pub fn free_port() -> Result<u16, Box<dyn Error>> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// Wait until something accepts connections on `port`, failing if `child` exits first or
+/// `timeout` passes.
+pub fn wait_for_port(port: u16, child: &mut Child, timeout: Duration) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    loop {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(format!("The service exited with {status} before listening on port {port}").into());
+        }
+        if started.elapsed() > timeout {
+            return Err(format!("The service did not listen on port {port} within {}s", timeout.as_secs()).into());
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Like [`wait_for_port`] for a port published by a container engine. Its proxy accepts
+/// connections right away, so the port only counts as listening once a connection stays open.
+pub fn wait_for_published_port(port: u16, child: &mut Child, timeout: Duration) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    loop {
+        if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) {
+            stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+            // The proxy closes the connection at once if nothing listens in the container
+            if !matches!(stream.read(&mut [0; 1]), Ok(0)) {
+                return Ok(());
+            }
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(format!("The container exited with {status} before listening on port {port}").into());
+        }
+        if started.elapsed() > timeout {
+            return Err(format!("The container did not listen on port {port} within {}s", timeout.as_secs()).into());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// The port the service listens on inside a container, Cloud Run's default.
+const CONTAINER_PORT: u16 = 8080;
+
+/// An image built on this machine with docker or podman.
+pub struct Image {
+    pub engine: &'static str,
+    pub name: String,
+}
+
+impl Image {
+    /// Build the staged context and Dockerfile `deploy` uploads for `pkg`.
+    pub fn build(workspace: &Workspace, pkg: &Value, config: &ServiceConfig) -> Result<Self, Box<dyn Error>> {
+        let engine = container_engine()?;
+        let context = context::stage(workspace, pkg, config)?;
+        let name = format!("cloudrun-local/{}", config.service);
+        println!("Building {name} with {engine}");
+        // Cloud Run only runs linux/amd64 images
+        let status = Command::new(engine)
+            .args(["build", "--platform", "linux/amd64", "--tag", &name])
+            .arg(&context)
+            .status()
+            .map_err(|err| format!("Failed to run {engine}: {err}"))?;
+        if !status.success() {
+            return Err(format!("`{engine} build` failed").into());
+        }
+        Ok(Image { engine, name })
+    }
+
+    /// The command running the image in a container named `container` as Cloud Run would:
+    /// published on `port`, with the service's memory and CPU limits, Cloud Run's env and `env`.
+    /// Arguments added to it are passed to the service.
+    pub fn run_command(
+        &self,
+        config: &ServiceConfig,
+        port: u16,
+        container: &str,
+        env: &[(String, String)],
+    ) -> Result<Command, Box<dyn Error>> {
+        let mut command = Command::new(self.engine);
+        command
+            .args(["run", "--rm", "--init", "--platform", "linux/amd64", "--name", container])
+            .arg("--publish")
+            .arg(format!("127.0.0.1:{port}:{CONTAINER_PORT}"))
+            .args(["--memory", &container_memory(config.memory.as_deref().unwrap_or("512Mi"))?])
+            .args(["--cpus", &container_cpus(config.cpu.as_deref().unwrap_or("1"))?]);
+        for (key, value) in cloud_run_env(config, CONTAINER_PORT).into_iter().chain(env.iter().cloned()) {
+            command.arg("--env").arg(format!("{key}={value}"));
+        }
+        command.arg(&self.name);
+        Ok(command)
+    }
+}
+
+/// A started service, stopped by killing it and removing its container if it has one.
+pub struct Running {
+    pub child: Child,
+    /// Engine and name of the container.
+    pub container: Option<(&'static str, String)>,
+}
+
+impl Running {
+    pub fn stop(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some((engine, name)) = self.container {
+            // Killing the client leaves the container running
+            let _ = Command::new(engine)
+                .args(["rm", "--force", &name])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+    }
+
+    /// Wait until the service accepts connections on `port`, see [`wait_for_port`] and
+    /// [`wait_for_published_port`].
+    pub fn wait_for_port(&mut self, port: u16, timeout: Duration) -> Result<(), Box<dyn Error>> {
+        match self.container {
+            Some(_) => wait_for_published_port(port, &mut self.child, timeout),
+            None => wait_for_port(port, &mut self.child, timeout),
+        }
+    }
+}
+
+/// `docker`, or `podman` if docker is not installed.
+pub fn container_engine() -> Result<&'static str, Box<dyn Error>> {
+    ["docker", "podman"]
+        .into_iter()
+        .find(|engine| Command::new(engine).arg("--version").output().is_ok_and(|o| o.status.success()))
+        .ok_or_else(|| "Neither docker nor podman is installed".into())
+}
+
+/// Cloud Run's memory limit, e.g. `512Mi` or `1G`, in the format of `docker run --memory`.
+pub fn container_memory(memory: &str) -> Result<String, Box<dyn Error>> {
+    let digits = memory.find(|c: char| !c.is_ascii_digit()).unwrap_or(memory.len());
+    let (number, unit) = memory.split_at(digits);
+    let number = number.parse::<u64>().map_err(|_| format!("Invalid memory limit '{memory}'"))?;
+    let bytes = match unit {
+        "" => number,
+        "k" | "K" => number * 1000,
+        "M" => number * 1000 * 1000,
+        "G" => number * 1000 * 1000 * 1000,
+        "Ki" => return Ok(format!("{number}k")),
+        "Mi" => return Ok(format!("{number}m")),
+        "Gi" => return Ok(format!("{number}g")),
+        _ => return Err(format!("Invalid memory limit '{memory}'").into()),
+    };
+    Ok(bytes.to_string())
+}
+
+/// Cloud Run's CPU limit, e.g. `2` or `500m`, in the format of `docker run --cpus`.
+pub fn container_cpus(cpu: &str) -> Result<String, Box<dyn Error>> {
+    let cpus = match cpu.strip_suffix('m') {
+        Some(millis) => millis.parse::<f64>().map(|m| m / 1000.0),
+        None => cpu.parse::<f64>(),
+    };
+    cpus.map(|cpus| cpus.to_string()).map_err(|_| format!("Invalid CPU limit '{cpu}'").into())
+}
+
+/// Send `GET path` and return the response's status code.
+pub fn http_get(port: u16, path: &str) -> Option<u16> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost:{port}\r\nConnection: close\r\n\r\n").ok()?;
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).ok()?;
+    status_line.split_whitespace().nth(1)?.parse().ok()
+}
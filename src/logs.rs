@@ -0,0 +1,282 @@
+use crate::config::ServiceConfig;
+use crate::metadata::Workspace;
+use crate::output::human_bytes;
+use crate::{gcloud, LogsArgs};
+use console::{style, Style};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often `--follow` polls Cloud Logging for new entries.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Read (and optionally tail) the Cloud Run logs of the resolved service.
+pub fn handle_logs(args: &LogsArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = Workspace::load()?;
+    let pkg = workspace.find_root_package()?;
+    let config = ServiceConfig::load(pkg, args.environment.as_deref())?;
+    let filter = build_filter(&config, args);
+
+    // An explicit start time replaces the default `--since` window
+    let freshness = if args.from.is_some() { None } else { args.since.as_deref() };
+    // Following starts at the window, gcloud's default one would reach back a whole day
+    let mut last_timestamp = match (args.follow, freshness) {
+        (true, Some(since)) => Some(window_start(since, SystemTime::now())?),
+        _ => None,
+    };
+    let mut entries = read(&config, &filter, freshness, args.limit)?;
+    // `gcloud logging read` returns the newest entries first
+    entries.reverse();
+
+    let mut seen = HashSet::new();
+    for entry in &entries {
+        print_entry(entry, args.raw);
+        remember(entry, &mut seen, &mut last_timestamp);
+    }
+
+    if !args.follow {
+        return Ok(());
+    }
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let filter = match &last_timestamp {
+            // `>=` so entries sharing the last timestamp are not lost; duplicates are skipped below
+            Some(ts) => format!("{filter} AND timestamp>=\"{ts}\""),
+            None => filter.clone(),
+        };
+        let mut entries = read(&config, &filter, None, 1000)?;
+        entries.reverse();
+        for entry in &entries {
+            let id = entry.get("insertId").and_then(Value::as_str).unwrap_or_default();
+            if seen.contains(id) {
+                continue;
+            }
+            print_entry(entry, args.raw);
+            remember(entry, &mut seen, &mut last_timestamp);
+        }
+    }
+}
+
+/// Build a Cloud Logging filter for the service and the requested revision/severity/time/trace.
+fn build_filter(config: &ServiceConfig, args: &LogsArgs) -> String {
+    let mut clauses = vec![
+        "resource.type=\"cloud_run_revision\"".to_string(),
+        format!("resource.labels.service_name=\"{}\"", config.service),
+    ];
+    if let Some(region) = &config.region {
+        clauses.push(format!("resource.labels.location=\"{region}\""));
+    }
+    if let Some(revision) = &args.revision {
+        clauses.push(format!("resource.labels.revision_name=\"{revision}\""));
+    }
+    if let Some(severity) = &args.severity {
+        clauses.push(format!("severity>={}", severity.to_uppercase()));
+    }
+    if let Some(from) = &args.from {
+        clauses.push(format!("timestamp>=\"{from}\""));
+    }
+    if let Some(to) = &args.to {
+        clauses.push(format!("timestamp<=\"{to}\""));
+    }
+    if let Some(trace) = &args.trace {
+        // Matches both bare trace ids and `projects/<p>/traces/<id>`
+        clauses.push(format!("trace:\"{trace}\""));
+    }
+    clauses.join(" AND ")
+}
+
+fn read(
+    config: &ServiceConfig,
+    filter: &str,
+    freshness: Option<&str>,
+    limit: usize,
+) -> Result<Vec<Value>, Box<dyn Error>> {
+    let mut cmd_args = vec![
+        "logging".to_string(),
+        "read".to_string(),
+        filter.to_string(),
+        format!("--limit={limit}"),
+    ];
+    if let Some(project) = &config.project {
+        cmd_args.push(format!("--project={project}"));
+    }
+    if let Some(freshness) = freshness {
+        cmd_args.push(format!("--freshness={freshness}"));
+    }
+    let entries = gcloud::json(&cmd_args)?;
+    Ok(entries.as_array().cloned().unwrap_or_default())
+}
+
+/// The start of a `--since` window like `10m`, `2h` or `1d` before `now`, as an RFC 3339
+/// timestamp in UTC.
+fn window_start(since: &str, now: SystemTime) -> Result<String, Box<dyn Error>> {
+    let invalid = || format!("Invalid --since '{since}', expected e.g. 30s, 10m, 2h or 1d");
+    let digits = since.find(|c: char| !c.is_ascii_digit()).unwrap_or(since.len());
+    let (number, unit) = since.split_at(digits);
+    let number = number.parse::<u64>().map_err(|_| invalid())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid().into()),
+    };
+    let start = now.duration_since(UNIX_EPOCH)?.as_secs().saturating_sub(number * unit);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let (days, secs) = (start / 86400, start % 86400);
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    Ok(format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    ))
+}
+
+fn remember(entry: &Value, seen: &mut HashSet<String>, last_timestamp: &mut Option<String>) {
+    if let Some(id) = entry.get("insertId").and_then(Value::as_str) {
+        seen.insert(id.to_string());
+    }
+    if let Some(ts) = entry.get("timestamp").and_then(Value::as_str) {
+        *last_timestamp = Some(ts.to_string());
+    }
+}
+
+/// Print a log entry as a single line: time, severity, revision, message, then
+/// the remaining structured fields and the request/trace it belongs to.
+fn print_entry(entry: &Value, raw: bool) {
+    if raw {
+        println!("{entry}");
+        return;
+    }
+
+    let timestamp = entry.get("timestamp").and_then(Value::as_str).unwrap_or_default();
+    let timestamp = timestamp.split('.').next().unwrap_or(timestamp).replacen('T', " ", 1);
+    let severity = entry.get("severity").and_then(Value::as_str).unwrap_or("DEFAULT");
+    let revision = entry
+        .pointer("/resource/labels/revision_name")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    let mut line = format!(
+        "{} {} {}",
+        style(timestamp).dim(),
+        severity_style(severity).apply_to(format!("{severity:<8}")),
+        style(revision).cyan(),
+    );
+
+    if let Some(request) = entry.get("httpRequest") {
+        line.push(' ');
+        line.push_str(&format_http_request(request));
+    }
+
+    if let Some(text) = entry.get("textPayload").and_then(Value::as_str) {
+        line.push(' ');
+        line.push_str(text.trim_end());
+    } else if let Some(Value::Object(payload)) = entry.get("jsonPayload") {
+        // tracing's JSON format nests the message and the event's fields in `fields`
+        let fields = payload.get("fields").and_then(Value::as_object);
+        let message = ["message", "msg"]
+            .iter()
+            .find_map(|key| payload.get(*key).and_then(Value::as_str))
+            .or_else(|| fields.and_then(|f| f.get("message")).and_then(Value::as_str));
+        if let Some(message) = message {
+            line.push(' ');
+            line.push_str(message.trim_end());
+        }
+        let nested = fields.into_iter().flatten().filter(|(key, _)| *key != "message");
+        for (key, value) in payload.iter().filter(|(key, _)| *key != "fields" || fields.is_none()).chain(nested) {
+            if matches!(key.as_str(), "message" | "msg") {
+                continue;
+            }
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            line.push_str(&format!(" {}={value}", style(key).dim()));
+        }
+    }
+
+    if let Some(trace) = entry.get("trace").and_then(Value::as_str) {
+        let id = trace.rsplit('/').next().unwrap_or(trace);
+        line.push_str(&format!(" {}={id}", style("trace").dim()));
+    }
+
+    println!("{line}");
+}
+
+/// e.g. `GET /users 200 12ms 1.2KiB`
+fn format_http_request(request: &Value) -> String {
+    let method = request.get("requestMethod").and_then(Value::as_str).unwrap_or("-");
+    let url = request.get("requestUrl").and_then(Value::as_str).unwrap_or("-");
+    // Strip scheme and host, the service is already known
+    let path = url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+        .unwrap_or(url);
+    let status = request.get("status").and_then(Value::as_u64).unwrap_or(0);
+    let status_style = match status {
+        500.. => Style::new().red(),
+        400.. => Style::new().yellow(),
+        _ => Style::new().green(),
+    };
+    let latency = request
+        .get("latency")
+        .and_then(Value::as_str)
+        .and_then(|l| l.trim_end_matches('s').parse::<f64>().ok())
+        .map(|secs| format!(" {:.0}ms", secs * 1000.0))
+        .unwrap_or_default();
+    let size = request
+        .get("responseSize")
+        .and_then(Value::as_str)
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(|bytes| format!(" {}", human_bytes(bytes)))
+        .unwrap_or_default();
+
+    format!("{method} {path} {}{latency}{size}", status_style.apply_to(status))
+}
+
+fn severity_style(severity: &str) -> Style {
+    match severity {
+        "EMERGENCY" | "ALERT" | "CRITICAL" | "ERROR" => Style::new().red().bold(),
+        "WARNING" => Style::new().yellow(),
+        "NOTICE" | "INFO" => Style::new().green(),
+        "DEBUG" => Style::new().blue(),
+        _ => Style::new().dim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_window_before_now() {
+        // 2024-03-01T00:30:00Z, the day after a leap day
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_253_000);
+        let cases = [
+            ("0s", "2024-03-01T00:30:00Z"),
+            ("45m", "2024-02-29T23:45:00Z"),
+            ("2h", "2024-02-29T22:30:00Z"),
+            ("366d", "2023-03-01T00:30:00Z"),
+        ];
+        for (since, start) in cases {
+            assert_eq!(window_start(since, now).unwrap(), start, "{since}");
+        }
+        for since in ["", "h", "10", "1h30m", "2w"] {
+            assert!(window_start(since, now).is_err(), "{since}");
+        }
+    }
+}
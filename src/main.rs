@@ -1,10 +1,30 @@
 use clap::{Args, Parser, Subcommand};
-use serde_json::Value;
-use std::path::{Path, PathBuf};
-use std::process::{exit, Command, Stdio};
-use std::{env, fs, thread};
-
+use std::env;
+use std::path::PathBuf;
+use std::process::exit;
+
+mod bench;
+mod buildlog;
+mod cloudbuild;
+mod config;
+mod context;
+mod delete;
+mod dev;
+mod deploy;
+mod dockerfile;
+mod gcloud;
+mod git;
 mod init;
+mod local;
+mod logs;
+mod metadata;
+mod output;
+mod promote;
+mod replay;
+mod run;
+mod services;
+mod status;
+mod table;
 #[derive(Parser)] // requires `derive` feature
 #[command(name = "cargo")]
 #[command(bin_name = "cargo")]
@@ -26,14 +46,280 @@ enum Commands {
     Deploy(DeployArgs),
     Init, // No additional args needed for Init
     New(NewArgs), // Assuming NewArgs might differ from InitArgs
+    Promote(PromoteArgs),
+    Status(StatusArgs),
+    List(ListArgs),
+    Logs(LogsArgs),
+    Delete(DeleteArgs),
+    Context(ContextArgs),
+    BenchStart(BenchStartArgs),
+    Run(RunArgs),
+    Dev(DevArgs),
 }
 
 #[derive(Args, Debug)]
 struct DeployArgs {
+    /// Named environment from `[package.metadata.cloudrun.environments]` to deploy to.
+    #[arg(long, short = 'e')]
+    environment: Option<String>,
+
+    /// Deploy every workspace member with a `[package.metadata.cloudrun]` section.
+    #[arg(long)]
+    workspace: bool,
+
+    /// Package or service to leave out of a `--workspace` deploy. May be repeated.
+    #[arg(long, requires = "workspace")]
+    exclude: Vec<String>,
+
+    /// Only deploy services whose sources or path dependencies changed since this git ref.
+    #[arg(long, requires = "workspace", value_name = "REF")]
+    changed_since: Option<String>,
+
+    /// How many services of a `--workspace` deploy run at the same time.
+    #[arg(long, short = 'j', default_value_t = 4)]
+    jobs: usize,
+
+    /// Deploy even if the live revision was built from the same source with the same config.
+    #[arg(long)]
+    force: bool,
+
+    /// Cargo features to build with, replacing `features` from the metadata. May be repeated.
+    #[arg(long, value_delimiter = ',')]
+    features: Vec<String>,
+
+    /// Build without the package's default features.
+    #[arg(long)]
+    no_default_features: bool,
+
+    /// Cargo profile to build with, e.g. `release-lto`.
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// RUSTFLAGS for the build in the image.
+    #[arg(long, allow_hyphen_values = true)]
+    rustflags: Option<String>,
+
+    /// Env var set while building the image, but not at runtime. May be repeated.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    build_env: Vec<(String, String)>,
+
+    /// Default for an `ARG` of the build stage. May be repeated.
+    #[arg(long = "build-arg", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    build_args: Vec<(String, String)>,
+
+    /// Vendor all dependencies into the build context, fetching private ones with local credentials.
+    #[arg(long)]
+    vendor: bool,
+
+    /// Build with thin LTO, `panic = "abort"` and stripped symbols, and report the binary and image size.
+    #[arg(long)]
+    optimize: bool,
+
+    /// Build with a rendered cloudbuild.yaml and `gcloud builds submit`, then deploy the image.
+    #[arg(long)]
+    cloud_build: bool,
+
     /// Additional flags or arguments to pass through to `gcloud`.
     #[arg(trailing_var_arg = true)]
     extra_args: Vec<String>,
 }
+
+/// Deploy the image serving traffic in one environment to another, without rebuilding.
+#[derive(Args, Debug)]
+struct PromoteArgs {
+    /// Environment whose serving image is promoted.
+    #[arg(long)]
+    from: String,
+
+    /// Environment to deploy the image to.
+    #[arg(long)]
+    to: String,
+
+    /// Additional flags or arguments to pass through to `gcloud`.
+    #[arg(trailing_var_arg = true)]
+    extra_args: Vec<String>,
+}
+
+/// Show URL, readiness, revisions and traffic of every service in the workspace.
+#[derive(Args, Debug)]
+struct StatusArgs {
+    /// Named environment from `[package.metadata.cloudrun.environments]` to inspect.
+    #[arg(long, short = 'e')]
+    environment: Option<String>,
+}
+
+/// List all Cloud Run services in a project/region.
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Project to list services of (defaults to the gcloud configuration).
+    #[arg(long)]
+    project: Option<String>,
+
+    /// Region to list services in (defaults to all regions).
+    #[arg(long)]
+    region: Option<String>,
+}
+
+/// Read and tail the logs of the service.
+#[derive(Args, Debug)]
+struct LogsArgs {
+    /// Named environment from `[package.metadata.cloudrun.environments]` to read logs of.
+    #[arg(long, short = 'e')]
+    environment: Option<String>,
+
+    /// Keep polling for new entries.
+    #[arg(long, short = 'f')]
+    follow: bool,
+
+    /// Only show logs of this revision.
+    #[arg(long)]
+    revision: Option<String>,
+
+    /// Minimum severity, e.g. `warning` or `error`.
+    #[arg(long, short = 's')]
+    severity: Option<String>,
+
+    /// Only show entries newer than this, e.g. `10m`, `2h` or `1d`.
+    #[arg(long, default_value = "1h", conflicts_with = "from")]
+    since: Option<String>,
+
+    /// Only show entries at or after this RFC 3339 timestamp.
+    #[arg(long)]
+    from: Option<String>,
+
+    /// Only show entries at or before this RFC 3339 timestamp.
+    #[arg(long)]
+    to: Option<String>,
+
+    /// Only show entries belonging to this trace id.
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// Maximum number of entries to read initially.
+    #[arg(long, short = 'n', default_value_t = 100)]
+    limit: usize,
+
+    /// Print entries as raw JSON instead of pretty-printing them.
+    #[arg(long)]
+    raw: bool,
+}
+
+/// Delete the service and optionally everything left behind by it.
+#[derive(Args, Debug)]
+struct DeleteArgs {
+    /// Named environment from `[package.metadata.cloudrun.environments]` to delete from.
+    #[arg(long, short = 'e')]
+    environment: Option<String>,
+
+    /// Also delete Eventarc triggers delivering to the service.
+    #[arg(long)]
+    triggers: bool,
+
+    /// Also delete Cloud Scheduler jobs calling the service's URL.
+    #[arg(long)]
+    scheduler_jobs: bool,
+
+    /// Also delete the images of all revisions from the registry.
+    #[arg(long)]
+    images: bool,
+
+    /// Shorthand for `--triggers --scheduler-jobs --images`.
+    #[arg(long)]
+    all: bool,
+
+    /// Do not ask for confirmation.
+    #[arg(long, short = 'y')]
+    yes: bool,
+}
+
+/// List the files `deploy` would upload and their total size.
+#[derive(Args, Debug)]
+struct ContextArgs {
+    /// Sort files by size, largest first.
+    #[arg(long)]
+    largest: bool,
+
+    /// Warn if the upload is larger than this many MiB.
+    #[arg(long, default_value_t = 100)]
+    warn_size: u64,
+}
+
+/// Build the service and start it repeatedly, measuring how long it takes to come up.
+#[derive(Args, Debug)]
+struct BenchStartArgs {
+    /// Named environment whose env vars the service is started with.
+    #[arg(long, short = 'e')]
+    environment: Option<String>,
+
+    /// How many times to start the service.
+    #[arg(long, short = 'n', default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    runs: u32,
+
+    /// Path requested to check that the service answers.
+    #[arg(long, default_value = "/")]
+    path: String,
+
+    /// Seconds to wait for a single start.
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Build the image `deploy` would build with docker or podman and start that instead.
+    #[arg(long)]
+    container: bool,
+}
+
+/// Build the service and run it locally with the env Cloud Run provides.
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Named environment whose env vars the service is started with.
+    #[arg(long, short = 'e')]
+    environment: Option<String>,
+
+    /// Port to listen on, instead of a random free one.
+    #[arg(long, short = 'p')]
+    port: Option<u16>,
+
+    /// File with `KEY=VALUE` lines to add to the env, `.env` next to `Cargo.toml` by default.
+    #[arg(long)]
+    env_file: Option<PathBuf>,
+
+    /// Env var to set, overriding the metadata and the env file. May be repeated.
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    env: Vec<(String, String)>,
+
+    /// Build the image `deploy` would build with docker or podman and run that instead.
+    #[arg(long)]
+    container: bool,
+
+    /// Rebuild and restart on changes to the package or its path dependencies.
+    #[arg(long, short = 'w')]
+    watch: bool,
+
+    /// Send the last CloudEvent the service received again after every restart.
+    #[arg(long, requires = "watch")]
+    replay_event: bool,
+
+    /// Arguments passed to the service.
+    #[arg(trailing_var_arg = true)]
+    args: Vec<String>,
+}
+
+/// Run every service of the workspace locally, each on its own port.
+#[derive(Args, Debug)]
+struct DevArgs {
+    /// Named environment whose env vars the services are started with.
+    #[arg(long, short = 'e')]
+    environment: Option<String>,
+
+    /// Package or service not to start. May be repeated.
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Give the services consecutive ports starting at this one, instead of random free ones.
+    #[arg(long)]
+    base_port: Option<u16>,
+}
+
 #[derive(Args, Debug)]
 struct NewArgs {
     /// The name of the package to create
@@ -67,7 +353,75 @@ fn main() {
     match &cli {
         CargoCli::CloudRun(cli) => {
             match &cli.command {
-                Commands::Deploy(deploy_args) => deploy(deploy_args),
+                Commands::Deploy(deploy_args) => {
+                    if let Err(err) = deploy::handle_deploy(deploy_args) {
+                        eprintln!("Failed to deploy: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::Promote(promote_args) => {
+                    if let Err(err) = promote::handle_promote(promote_args) {
+                        eprintln!("Failed to promote: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::Status(status_args) => {
+                    if let Err(err) = status::handle_status(status_args) {
+                        eprintln!("Failed to get service status: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::List(list_args) => {
+                    if let Err(err) = status::handle_list(list_args) {
+                        eprintln!("Failed to list services: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::Logs(logs_args) => {
+                    if let Err(err) = logs::handle_logs(logs_args) {
+                        eprintln!("Failed to read logs: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::Delete(delete_args) => {
+                    if let Err(err) = delete::handle_delete(delete_args) {
+                        eprintln!("Failed to delete service: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::Context(context_args) => {
+                    if let Err(err) = context::handle_context(context_args) {
+                        eprintln!("Failed to list the build context: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::BenchStart(bench_args) => {
+                    if let Err(err) = bench::handle_bench_start(bench_args) {
+                        eprintln!("Failed to benchmark the start: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::Run(run_args) => {
+                    if let Err(err) = run::handle_run(run_args) {
+                        eprintln!("Failed to run: {err}");
+                        exit(1);
+                    }
+                },
+
+                Commands::Dev(dev_args) => {
+                    if let Err(err) = dev::handle_dev(dev_args) {
+                        eprintln!("Failed to run the services: {err}");
+                        exit(1);
+                    }
+                },
 
                 Commands::New(new_args) => {
                     if let Err(err) = init::handle_new(new_args) {
@@ -106,251 +460,10 @@ fn main() {
     }
 }
 
-fn deploy(args: &DeployArgs) {
-    // 1. Find the workspace root and the root package name
-    let (root_dir, root_package_name) = match find_root_package() {
-        Ok(pair) => pair,
-        Err(err) => {
-            eprintln!("Failed to determine root package: {err}");
-            exit(1);
-        }
-    };
-
-    // 2. Change directory to the root package directory
-    if let Err(err) = env::set_current_dir(&root_dir) {
-        eprintln!(
-            "Failed to change directory to {}: {err}",
-            root_dir.display()
-        );
-        exit(1);
+/// Parse a `KEY=VALUE` argument.
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{arg}'")),
     }
-
-    // 3. Build the Dockerfile content, referencing the found package name
-    let dockerfile_content = format!(
-        r#"
-# https://hub.docker.com/_/rust
-FROM rust:1 as build-env
-WORKDIR /app
-COPY . /app
-RUN cargo build --release
-
-FROM gcr.io/distroless/cc-debian12
-ENV PORT 8080
-COPY --from=build-env /app/target/release/{} /
-ENTRYPOINT ["/{}"]
-"#,
-        root_package_name,
-        root_package_name
-    );
-
-    // if Rc::new(fs::File("Dockerfile")) {}
-    let mut delete_dockerfile = false;
-    if File::open(root_dir.join("Dockerfile")).is_err() {
-        // 4. Write the Dockerfile in the crate root
-        let dockerfile_path = root_dir.join("Dockerfile");
-        if let Err(err) = fs::write(&dockerfile_path, &dockerfile_content) {
-            eprintln!("Failed to write Dockerfile: {err}");
-            exit(1);
-        }
-        delete_dockerfile = true;
-    }
-
-    if !root_dir.join(".gcloudignore").exists() {
-        if let Err(e) = create_gcloudignore() {
-            let gcloudignore_path = root_dir.join(".gcloudignore");
-            eprintln!("Warning: Failed to create {}: {}", gcloudignore_path.display(), e);
-        }
-    }
-
-    // let previous_image = Command::new("gcloud")
-    //     .args([
-    //         "run",
-    //         "services",
-    //         "describe",
-    //         &root_package_name,
-    //         "--format=value(image)"
-    //     ])
-    //     .output()
-    //     .ok()
-    //     .and_then(|output| {
-    //         if output.status.success() {
-    //             String::from_utf8_lossy(&output.stdout)
-    //                 .trim()
-    //                 .to_string()
-    //                 .into()
-    //         } else {
-    //             None
-    //         }
-    //     })
-    //     .filter(|s| !s.is_empty())
-    //     .map(|s| format!("--cache-from={}", s))
-    //     .unwrap_or_default();
-
-    let mut cmd_args = vec![
-        "run".to_string(),
-        "deploy".to_string(),
-        root_package_name.clone(),
-        "--source".to_string(),
-        ".".to_string(),
-        "--allow-unauthenticated".to_string(),
-        "--use-http2".to_string()
-    ];
-
-    // if !previous_image.is_empty() {
-    //     cmd_args.push(previous_image);
-    // }
-    
-    // Add any additional arguments from DeployArgs
-    if !args.extra_args.is_empty() {
-        if !cmd_args.is_empty() {
-            cmd_args.push("--".to_string());
-        }
-        cmd_args.extend(args.extra_args.iter().cloned());
-    }
-    
-    let status = Command::new("gcloud")
-        .args(&cmd_args)
-        .status()
-        .expect("Failed to spawn gcloud process");
-
-    if !status.success() {
-        eprintln!("gcloud run deploy failed with status: {:?}", status.code());
-        maybe_delete_dockerfile(&mut delete_dockerfile);
-        exit(1);
-    }
-    maybe_delete_dockerfile(&mut delete_dockerfile);
-}
-
-fn maybe_delete_dockerfile(delete_dockerfile: &mut bool) {
-    if *delete_dockerfile {
-        if let Err(e) = fs::remove_file("Dockerfile") {
-            eprintln!("Warning: Failed to delete temporary Dockerfile: {}", e);
-        }
-    }
-}
-
-/// Find the Cargo workspace root and the *root package name* using `cargo metadata`.
-    /// Returns a tuple: (workspace_root_path, root_package_name).
-    ///
-/// If the workspace root has a virtual manifest (no package in root), falls back to using
-/// the current package but still deploys from the workspace root to maintain dependencies.
-fn find_root_package() -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
-    // Run `cargo metadata --format-version=1`
-    let output = Command::new("cargo")
-        .args(["metadata", "--format-version=1"])
-        .output()?;
-
-    if !output.status.success() {
-        return Err("`cargo metadata` failed".into());
-    }
-
-    // Parse JSON
-    let v: Value = serde_json::from_slice(&output.stdout)?;
-
-    // Extract workspace_root
-    let Some(workspace_root_str) = v.get("workspace_root").and_then(Value::as_str) else {
-        return Err("No 'workspace_root' found in cargo metadata".into());
-    };
-    let workspace_root = PathBuf::from(workspace_root_str);
-
-    // Look through the "packages" array
-    let Some(packages) = v.get("packages").and_then(Value::as_array) else {
-        return Err("'packages' not found or is not an array in cargo metadata".into());
-    };
-
-    let manifest_path = workspace_root
-        .join("Cargo.toml")
-        .to_string_lossy()
-        .to_string();
-
-    // First try to find a package at the workspace root
-    for pkg in packages {
-        let pkg_manifest_path = pkg
-            .get("manifest_path")
-            .and_then(Value::as_str)
-            .unwrap_or_default();
-
-        // Compare them in a platform-agnostic way
-        if same_file_path(&pkg_manifest_path, &manifest_path) {
-            // Found the root package
-            let Some(pkg_name) = pkg.get("name").and_then(Value::as_str) else {
-                return Err("Package in root has no 'name' in cargo metadata".into());
-            };
-            return Ok((workspace_root, pkg_name.to_owned()));
-        }
-    }
-
-    // No package at workspace root (virtual manifest) - find the current package instead,
-    // but still return the workspace root as the directory to build from
-    let current_dir = env::current_dir()?;
-    let mut current_package_name = None;
-    
-    // Try to find a package that contains the current directory
-    for pkg in packages {
-        let Some(pkg_manifest_path) = pkg.get("manifest_path").and_then(Value::as_str) else {
-            continue;
-        };
-        
-        // Get the directory of the package manifest
-        let pkg_dir = Path::new(pkg_manifest_path).parent().unwrap_or(Path::new(""));
-        
-        // Debug info
-        // eprintln!("Checking package at {}: current_dir={}", pkg_dir.display(), current_dir.display());
-        
-        // Check if the current directory starts with this package directory
-        // This is a simplified check - we might need a more robust method
-        if let Ok(rel_path) = current_dir.strip_prefix(pkg_dir) {
-            if !rel_path.as_os_str().is_empty() && rel_path.components().count() > 0 {
-                // We're not in the package directory, skip
-                continue;
-            }
-            
-            let Some(pkg_name) = pkg.get("name").and_then(Value::as_str) else {
-                continue;
-            };
-            
-            current_package_name = Some(pkg_name.to_owned());
-            break;
-        };
-    }
-
-    if let Some(package_name) = current_package_name {
-        return Ok((workspace_root, package_name));
-    }
-
-    Err("Could not find a suitable package to deploy. Neither a root package nor a package at the current directory was found.".into())
 }
-
-/// Compare two file paths in a slightly more robust way.
-/// (On Windows, e.g., backslash vs forward slash).
-fn same_file_path(a: &str, b: &str) -> bool {
-    // Convert both to a canonical PathBuf
-    let path_a = Path::new(a).components().collect::<Vec<_>>();
-    let path_b = Path::new(b).components().collect::<Vec<_>>();
-    path_a == path_b
-}
-
-use std::fs::File;
-use std::io::Write;
-
-fn create_gcloudignore() -> std::io::Result<()> {
-    let root_dir = match find_root_package() {
-        Ok((dir, _)) => dir,
-        Err(_) => PathBuf::from("."), // Fallback to current directory if can't determine workspace root
-    };
-    let gcloudignore_content = r#"# Rust build artifacts
-/target/
-/debug/
-/target/**/*
-.git
-.gitignore
-.gcloudignore"#;
-
-    let mut file = File::create(root_dir.join(".gcloudignore"))?;
-    file.write_all(gcloudignore_content.as_bytes())?;
-    Ok(())
-}
-
-
-
-
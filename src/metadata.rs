@@ -0,0 +1,234 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The parts of `cargo metadata` output that cargo-cloudrun cares about.
+pub struct Workspace {
+    pub root: PathBuf,
+    pub packages: Vec<Value>,
+    /// Package ids of the workspace members.
+    pub members: Vec<String>,
+    /// `[workspace.metadata]`, `null` if there is none.
+    pub metadata: Value,
+}
+
+impl Workspace {
+    /// Run `cargo metadata --format-version=1` in the current directory and parse the output.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        Self::load_with(&[])
+    }
+
+    /// Like [`Workspace::load`], but fails if `Cargo.lock` is missing or out of date
+    /// instead of writing it, the same way the `--locked` build in the image would.
+    pub fn load_locked() -> Result<Self, Box<dyn Error>> {
+        Self::load_with(&["--locked"]).map_err(|err| {
+            if !err.to_string().contains("--locked") {
+                return err;
+            }
+            let lockfile_exists = Command::new("cargo")
+                .args(["locate-project", "--workspace", "--message-format=plain"])
+                .output()
+                .ok()
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+                .is_some_and(|manifest| Path::new(&manifest).with_file_name("Cargo.lock").exists());
+            if lockfile_exists {
+                "Cargo.lock is out of date with Cargo.toml. Run `cargo update --workspace` and commit the result".into()
+            } else {
+                "Cargo.lock is missing. Run `cargo generate-lockfile` and commit it, \
+                 the image is built with `--locked`"
+                    .into()
+            }
+        })
+    }
+
+    fn load_with(extra_args: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let output = Command::new("cargo")
+            .args(["metadata", "--format-version=1"])
+            .args(extra_args)
+            .output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(match stderr.lines().find(|line| line.starts_with("error:")) {
+                Some(line) => format!("`cargo metadata` failed: {}", line.trim_start_matches("error:").trim()).into(),
+                None => "`cargo metadata` failed".into(),
+            });
+        }
+
+        // Parse JSON
+        let v: Value = serde_json::from_slice(&output.stdout)?;
+
+        // Extract workspace_root
+        let Some(workspace_root_str) = v.get("workspace_root").and_then(Value::as_str) else {
+            return Err("No 'workspace_root' found in cargo metadata".into());
+        };
+
+        // Look through the "packages" array
+        let Some(packages) = v.get("packages").and_then(Value::as_array) else {
+            return Err("'packages' not found or is not an array in cargo metadata".into());
+        };
+
+        let members = v
+            .get("workspace_members")
+            .and_then(Value::as_array)
+            .map(|ids| ids.iter().filter_map(Value::as_str).map(str::to_string).collect())
+            .unwrap_or_default();
+
+        Ok(Workspace {
+            root: PathBuf::from(workspace_root_str),
+            packages: packages.clone(),
+            members,
+            metadata: v.get("metadata").cloned().unwrap_or(Value::Null),
+        })
+    }
+
+    /// Packages that are members of the workspace (as opposed to dependencies).
+    pub fn member_packages(&self) -> impl Iterator<Item = &Value> {
+        self.packages.iter().filter(|pkg| {
+            pkg.get("id")
+                .and_then(Value::as_str)
+                .is_some_and(|id| self.members.iter().any(|m| m == id))
+        })
+    }
+
+    /// Workspace members that opt in to Cloud Run through `[package.metadata.cloudrun]`.
+    /// Falls back to the root package if no member does.
+    pub fn deployable_packages(&self) -> Result<Vec<&Value>, Box<dyn Error>> {
+        let opted_in = self
+            .member_packages()
+            .filter(|pkg| pkg.pointer("/metadata/cloudrun").is_some())
+            .collect::<Vec<_>>();
+        if !opted_in.is_empty() {
+            return Ok(opted_in);
+        }
+        Ok(vec![self.find_root_package()?])
+    }
+
+    /// Local packages that `pkg` depends on through `path = "..."`, directly or transitively.
+    /// Dev-dependencies are skipped since they do not end up in the deployed binary.
+    pub fn transitive_path_dependencies(&self, pkg: &Value) -> Vec<&Value> {
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![pkg];
+        let mut found = Vec::new();
+        while let Some(current) = stack.pop() {
+            for dep in self.path_dependencies(current) {
+                let id = dep.get("id").and_then(Value::as_str).unwrap_or_default();
+                if seen.insert(id) {
+                    found.push(dep);
+                    stack.push(dep);
+                }
+            }
+        }
+        found
+    }
+
+    /// Direct, non-dev path dependencies of `pkg`.
+    fn path_dependencies(&self, pkg: &Value) -> Vec<&Value> {
+        let Some(deps) = pkg.get("dependencies").and_then(Value::as_array) else {
+            return Vec::new();
+        };
+        deps.iter()
+            .filter(|dep| dep.get("kind").and_then(Value::as_str) != Some("dev"))
+            .filter_map(|dep| dep.get("path").and_then(Value::as_str))
+            .filter_map(|path| {
+                self.packages
+                    .iter()
+                    .find(|p| package_dir(p).is_some_and(|dir| same_file_path(&dir.to_string_lossy(), path)))
+            })
+            .collect()
+    }
+
+    /// The package whose directory is the closest parent of `file`, if any.
+    pub fn owning_package(&self, file: &Path) -> Option<&Value> {
+        self.packages
+            .iter()
+            .filter(|pkg| pkg.get("source").is_none_or(Value::is_null))
+            .filter_map(|pkg| Some((pkg, package_dir(pkg)?)))
+            .filter(|(_, dir)| file.starts_with(dir))
+            .max_by_key(|(_, dir)| dir.components().count())
+            .map(|(pkg, _)| pkg)
+    }
+
+    /// Find the *root package* of the workspace.
+    ///
+    /// If the workspace root has a virtual manifest (no package in root), falls back to using
+    /// the current package but still deploys from the workspace root to maintain dependencies.
+    pub fn find_root_package(&self) -> Result<&Value, Box<dyn Error>> {
+        let manifest_path = self
+            .root
+            .join("Cargo.toml")
+            .to_string_lossy()
+            .to_string();
+
+        // First try to find a package at the workspace root
+        for pkg in &self.packages {
+            let pkg_manifest_path = pkg
+                .get("manifest_path")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+
+            // Compare them in a platform-agnostic way
+            if same_file_path(pkg_manifest_path, &manifest_path) {
+                if pkg.get("name").and_then(Value::as_str).is_none() {
+                    return Err("Package in root has no 'name' in cargo metadata".into());
+                }
+                return Ok(pkg);
+            }
+        }
+
+        // No package at workspace root (virtual manifest) - find the current package instead,
+        // but still return the workspace root as the directory to build from
+        let current_dir = env::current_dir()?;
+
+        // Try to find a package that contains the current directory
+        for pkg in &self.packages {
+            let Some(pkg_manifest_path) = pkg.get("manifest_path").and_then(Value::as_str) else {
+                continue;
+            };
+
+            // Get the directory of the package manifest
+            let pkg_dir = Path::new(pkg_manifest_path).parent().unwrap_or(Path::new(""));
+
+            // Check if the current directory starts with this package directory
+            // This is a simplified check - we might need a more robust method
+            if let Ok(rel_path) = current_dir.strip_prefix(pkg_dir) {
+                if !rel_path.as_os_str().is_empty() && rel_path.components().count() > 0 {
+                    // We're not in the package directory, skip
+                    continue;
+                }
+
+                if pkg.get("name").and_then(Value::as_str).is_none() {
+                    continue;
+                }
+
+                return Ok(pkg);
+            };
+        }
+
+        Err("Could not find a suitable package to deploy. Neither a root package nor a package at the current directory was found.".into())
+    }
+}
+
+/// The `name` of a package entry from `cargo metadata`.
+pub fn package_name(pkg: &Value) -> &str {
+    pkg.get("name").and_then(Value::as_str).unwrap_or_default()
+}
+
+/// The directory containing the package's `Cargo.toml`.
+pub fn package_dir(pkg: &Value) -> Option<&Path> {
+    pkg.get("manifest_path")
+        .and_then(Value::as_str)
+        .and_then(|p| Path::new(p).parent())
+}
+
+/// Compare two file paths in a slightly more robust way.
+/// (On Windows, e.g., backslash vs forward slash).
+fn same_file_path(a: &str, b: &str) -> bool {
+    // Convert both to a canonical PathBuf
+    let path_a = Path::new(a).components().collect::<Vec<_>>();
+    let path_b = Path::new(b).components().collect::<Vec<_>>();
+    path_a == path_b
+}
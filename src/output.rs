@@ -0,0 +1,99 @@
+use console::Style;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+/// Colours cycled through for per-service prefixes.
+const COLOURS: [fn(Style) -> Style; 6] = [
+    Style::cyan,
+    Style::magenta,
+    Style::yellow,
+    Style::green,
+    Style::blue,
+    Style::red,
+];
+
+/// A coloured `name |` prefix put in front of every line a service prints.
+#[derive(Clone)]
+pub struct Prefix {
+    label: String,
+    style: Style,
+}
+
+impl Prefix {
+    /// `index` picks the colour, `width` pads the label so output of several services lines up.
+    pub fn new(name: &str, index: usize, width: usize) -> Self {
+        Prefix {
+            label: format!("{name:<width$} |"),
+            style: COLOURS[index % COLOURS.len()](Style::new()),
+        }
+    }
+
+    pub fn line(&self, line: &str) -> String {
+        format!("{} {line}", self.style.apply_to(&self.label))
+    }
+}
+
+/// Run `command`, printing each line of its stdout and stderr, with `prefix` if one is given.
+/// Returns the exit status along with every line printed.
+pub fn run_captured(command: &mut Command, prefix: Option<&Prefix>) -> io::Result<(ExitStatus, Vec<String>)> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let (out_lines, err_lines) = thread::scope(|scope| {
+        let out = scope.spawn(|| forward_lines(stdout, prefix, false));
+        let err = scope.spawn(|| forward_lines(stderr, prefix, true));
+        (
+            out.join().expect("output thread panicked"),
+            err.join().expect("output thread panicked"),
+        )
+    });
+
+    let status = child.wait()?;
+    Ok((status, out_lines.into_iter().chain(err_lines).collect()))
+}
+
+/// Print every line read from `reader`, with `prefix` if one is given, and return them.
+pub fn forward_lines(reader: impl Read, prefix: Option<&Prefix>, to_stderr: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    for_each_line(reader, prefix, to_stderr, |line| lines.push(line));
+    lines
+}
+
+/// Like [`forward_lines`], without keeping the lines, for processes that run until stopped.
+pub fn print_lines(reader: impl Read, prefix: Option<&Prefix>, to_stderr: bool) {
+    for_each_line(reader, prefix, to_stderr, drop);
+}
+
+fn for_each_line(reader: impl Read, prefix: Option<&Prefix>, to_stderr: bool, mut printed_line: impl FnMut(String)) {
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else { break };
+        // Progress spinners redraw with carriage returns, keep only the final state
+        let line = line.rsplit('\r').next().unwrap_or_default().to_string();
+        let printed = match prefix {
+            Some(prefix) => prefix.line(&line),
+            None => line.clone(),
+        };
+        if to_stderr {
+            eprintln!("{printed}");
+        } else {
+            println!("{printed}");
+        }
+        printed_line(line);
+    }
+}
+
+/// e.g. `1.2KiB`, `340.0MiB`
+pub fn human_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes}B"),
+        1024..1_048_576 => format!("{:.1}KiB", bytes as f64 / 1024.0),
+        1_048_576..1_073_741_824 => format!("{:.1}MiB", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1}GiB", bytes as f64 / 1_073_741_824.0),
+    }
+}
@@ -0,0 +1,97 @@
+use crate::config::ServiceConfig;
+use crate::{deploy, gcloud, git};
+use crate::metadata::Workspace;
+use crate::PromoteArgs;
+use serde_json::Value;
+use std::error::Error;
+
+/// Deploy the image serving traffic in one environment to another, without rebuilding.
+pub fn handle_promote(args: &PromoteArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = Workspace::load()?;
+    let pkg = workspace.find_root_package()?;
+    let from = ServiceConfig::load(pkg, Some(&args.from))?;
+    let mut to = ServiceConfig::load(pkg, Some(&args.to))?;
+    // `--set-env-vars` replaces all env vars, so the dependency URLs must be part of it
+    let deps = deploy::inject_deployed_dependencies(&workspace, Some(&args.to), &mut to)?;
+
+    // 1. Find the exact image digest serving traffic in the source environment
+    let source_flags = from.target_flags();
+    let service = gcloud::describe_service(&from.service, &source_flags)?;
+    let Some(revision) = gcloud::serving_revision(&service) else {
+        return Err(format!("Service '{}' in '{}' has no revision serving traffic", from.service, args.from).into());
+    };
+    let revision_json = gcloud::describe_revision(&revision, &source_flags)?;
+    let image = image_digest(&revision_json)
+        .ok_or_else(|| format!("Could not determine the image digest of revision '{revision}'"))?;
+    println!("'{}' in {} is serving {revision} ({image})", from.service, args.from);
+
+    // 2. Copy it into the target registry if it is not already there
+    let image = match to.registry() {
+        Some(registry) if !image.starts_with(&format!("{registry}/")) => {
+            copy_image(&image, &registry, &revision)?
+        }
+        _ => image,
+    };
+
+    // 3. Deploy that digest with the target environment's config
+    println!("Promoting {image} to '{}' in {}", to.service, args.to);
+    let mut cmd_args = vec![
+        "run".to_string(),
+        "deploy".to_string(),
+        to.service.clone(),
+        format!("--image={image}"),
+    ];
+    cmd_args.extend(to.deploy_flags());
+    // Keep recording which commit the image was built from
+    if let Some(sha) = revision_json
+        .pointer(&format!("/metadata/labels/{}", git::SHA_LABEL))
+        .and_then(Value::as_str)
+    {
+        cmd_args.push(format!("--update-labels={}={sha}", git::SHA_LABEL));
+    }
+    if !args.extra_args.is_empty() {
+        cmd_args.push("--".to_string());
+        cmd_args.extend(args.extra_args.iter().cloned());
+    }
+    gcloud::run(&cmd_args)?;
+    for dep in deploy::grant_invoker(&to, &deps.iter().collect::<Vec<_>>())? {
+        println!("Allowed '{}' to invoke '{dep}'", to.service);
+    }
+    Ok(())
+}
+
+/// The fully qualified `image@sha256:...` reference a revision was deployed from.
+fn image_digest(revision: &Value) -> Option<String> {
+    if let Some(digest) = revision.pointer("/status/imageDigest").and_then(Value::as_str) {
+        return Some(digest.to_string());
+    }
+    // Older revisions may only have the image in the spec; only trust it if it is pinned
+    revision
+        .pointer("/spec/containers/0/image")
+        .and_then(Value::as_str)
+        .filter(|image| image.contains("@sha256:"))
+        .map(str::to_string)
+}
+
+/// Copy `image` (pinned by digest) into `registry`, tagged with the source revision name.
+/// Returns the digest reference of the copy, which keeps the same digest.
+fn copy_image(image: &str, registry: &str, revision: &str) -> Result<String, Box<dyn Error>> {
+    let Some((repository, digest)) = image.split_once('@') else {
+        return Err(format!("Image '{image}' is not pinned by digest").into());
+    };
+    let name = repository.rsplit('/').next().unwrap_or(repository);
+    // Strip a tag if the repository part carries one
+    let name = name.split(':').next().unwrap_or(name);
+
+    let destination = format!("{registry}/{name}");
+    println!("Copying {image} to {destination}");
+    gcloud::run(&[
+        "container",
+        "images",
+        "add-tag",
+        image,
+        &format!("{destination}:{revision}"),
+        "--quiet",
+    ])?;
+    Ok(format!("{destination}@{digest}"))
+}
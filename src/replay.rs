@@ -0,0 +1,241 @@
+use serde_json::Value;
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A CloudEvent request as it was received, in binary or structured mode.
+struct Event {
+    kind: String,
+    request: Vec<u8>,
+}
+
+/// A proxy in front of the service that remembers the last CloudEvent sent through it,
+/// so it can be sent again after the service restarted.
+pub struct Recorder {
+    upstream: u16,
+    last: Arc<Mutex<Option<Event>>>,
+}
+
+impl Recorder {
+    /// Listen on `port` and forward every connection to the service on `upstream`.
+    pub fn start(port: u16, upstream: u16) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|err| format!("Failed to listen on port {port}: {err}"))?;
+        let last = Arc::new(Mutex::new(None));
+        let recorded = Arc::clone(&last);
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let recorded = Arc::clone(&recorded);
+                thread::spawn(move || {
+                    if let Err(err) = forward(client, upstream, &recorded) {
+                        eprintln!("Warning: failed to forward a request: {err}");
+                    }
+                });
+            }
+        });
+        Ok(Recorder { upstream, last })
+    }
+
+    /// Send the last recorded CloudEvent to the service again and print the response status.
+    pub fn replay(&self) {
+        let last = self.last.lock().expect("recorder lock poisoned");
+        let Some(event) = last.as_ref() else { return };
+        let status = send(self.upstream, &event.request)
+            .map(|status| format!("answered {status}"))
+            .unwrap_or_else(|err| format!("failed: {err}"));
+        println!("Sent the last CloudEvent ({}) again, {status}", event.kind);
+    }
+}
+
+/// Copy requests from `client` to the service and responses back, recording CloudEvents.
+fn forward(client: TcpStream, upstream: u16, last: &Mutex<Option<Event>>) -> io::Result<()> {
+    let mut client_writer = client.try_clone()?;
+    let mut service = match TcpStream::connect(("127.0.0.1", upstream)) {
+        Ok(service) => service,
+        Err(_) => {
+            // The service is being rebuilt
+            return client_writer.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        }
+    };
+    let mut service_reader = service.try_clone()?;
+    let responses = thread::spawn(move || {
+        let _ = io::copy(&mut service_reader, &mut client_writer);
+        let _ = client_writer.shutdown(Shutdown::Write);
+    });
+
+    let mut client = BufReader::new(client);
+    let result = (|| {
+        while let Some(request) = read_request(&mut client)? {
+            service.write_all(&request.bytes)?;
+            if let Some(kind) = request.cloud_event {
+                *last.lock().expect("recorder lock poisoned") = Some(Event { kind, request: request.bytes });
+            }
+            if !request.complete {
+                // Chunked bodies and anything but HTTP/1 are passed through without being recorded
+                io::copy(&mut client, &mut service)?;
+                break;
+            }
+        }
+        Ok(())
+    })();
+    // Also on errors, so the service answers what it got and the client's connection closes
+    let _ = service.shutdown(Shutdown::Write);
+    let _ = responses.join();
+    result
+}
+
+/// Cloud Run rejects HTTP/1 requests larger than this.
+const MAX_REQUEST_SIZE: usize = 32 * 1024 * 1024;
+
+struct Request {
+    bytes: Vec<u8>,
+    /// The event type if the request is a CloudEvent.
+    cloud_event: Option<String>,
+    /// Whether `bytes` holds the whole request, or only its head.
+    complete: bool,
+}
+
+/// Read one HTTP/1 request, or `None` once the client closed the connection.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut bytes = Vec::new();
+    if reader.read_until(b'\n', &mut bytes)? == 0 {
+        return Ok(None);
+    }
+    let request_line = String::from_utf8_lossy(&bytes).trim_end().to_string();
+    if !request_line.ends_with("HTTP/1.1") && !request_line.ends_with("HTTP/1.0") {
+        return Ok(Some(Request { bytes, cloud_event: None, complete: false }));
+    }
+
+    let mut content_length = 0;
+    let mut chunked = false;
+    let mut ce_type = None;
+    let mut structured = false;
+    loop {
+        let start = bytes.len();
+        if reader.read_until(b'\n', &mut bytes)? == 0 {
+            return Ok(Some(Request { bytes, cloud_event: None, complete: false }));
+        }
+        let line = String::from_utf8_lossy(&bytes[start..]).trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value
+                    .parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid Content-Length '{value}'")))?
+            }
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "ce-type" => ce_type = Some(value.to_string()),
+            "content-type" => structured = value.starts_with("application/cloudevents+json"),
+            _ => {}
+        }
+    }
+    if chunked {
+        return Ok(Some(Request { bytes, cloud_event: None, complete: false }));
+    }
+
+    let head = bytes.len();
+    if head + content_length > MAX_REQUEST_SIZE {
+        let message = format!("request of {content_length} bytes is larger than Cloud Run's limit of 32 MiB");
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    bytes.resize(head + content_length, 0);
+    reader.read_exact(&mut bytes[head..])?;
+    if structured {
+        ce_type = serde_json::from_slice::<Value>(&bytes[head..])
+            .ok()
+            .and_then(|event| event.get("type").and_then(Value::as_str).map(str::to_string))
+            .or(Some("structured".to_string()));
+    }
+    Ok(Some(Request { bytes, cloud_event: ce_type, complete: true }))
+}
+
+/// Send a raw request to the service and return the response status code.
+fn send(port: u16, request: &[u8]) -> Result<u16, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    stream.write_all(request)?;
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| "no response".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(mut input: &[u8]) -> Vec<Request> {
+        let mut requests = Vec::new();
+        while let Some(request) = read_request(&mut input).unwrap() {
+            requests.push(request);
+        }
+        requests
+    }
+
+    #[test]
+    fn records_binary_mode_events() {
+        let input = b"POST / HTTP/1.1\r\nce-type: order.created\r\nContent-Length: 2\r\n\r\n{}";
+        let requests = read_all(input);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].bytes, input);
+        assert_eq!(requests[0].cloud_event.as_deref(), Some("order.created"));
+        assert!(requests[0].complete);
+    }
+
+    #[test]
+    fn reads_type_of_structured_mode_events() {
+        let body = r#"{"specversion":"1.0","type":"order.paid","data":{}}"#;
+        let input = format!(
+            "POST / HTTP/1.1\r\nContent-Type: application/cloudevents+json; charset=utf-8\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let requests = read_all(input.as_bytes());
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].bytes, input.as_bytes());
+        assert_eq!(requests[0].cloud_event.as_deref(), Some("order.paid"));
+    }
+
+    #[test]
+    fn passes_chunked_bodies_through() {
+        let head = "POST / HTTP/1.1\r\nce-type: order.created\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let input = format!("{head}2\r\n{{}}\r\n0\r\n\r\n").into_bytes();
+        let mut reader = &input[..];
+        let request = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(request.bytes, head.as_bytes());
+        assert_eq!(request.cloud_event, None);
+        assert!(!request.complete);
+        // The body is left for the caller to copy
+        assert_eq!(reader, &input[head.len()..]);
+    }
+
+    #[test]
+    fn reads_requests_of_a_kept_alive_connection() {
+        let input = b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n\
+            POST / HTTP/1.1\r\nce-type: order.created\r\nContent-Length: 2\r\n\r\n{}";
+        let requests = read_all(input);
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].cloud_event, None);
+        assert!(requests[0].complete);
+        assert_eq!(requests[1].cloud_event.as_deref(), Some("order.created"));
+        assert_eq!([&requests[0].bytes[..], &requests[1].bytes[..]].concat(), input);
+    }
+
+    #[test]
+    fn rejects_invalid_and_oversized_bodies() {
+        for length in ["abc", "-1", "33554432"] {
+            let input = format!("POST / HTTP/1.1\r\nContent-Length: {length}\r\n\r\n");
+            let err = read_request(&mut input.as_bytes()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{length}");
+        }
+    }
+}
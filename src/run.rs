@@ -0,0 +1,203 @@
+use crate::config::{ServiceConfig, WorkspaceConfig};
+use crate::local::{Image, Running};
+use crate::metadata::{package_dir, Workspace};
+use crate::replay::Recorder;
+use crate::{context, local, RunArgs};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How long the service may take to listen, the same as Cloud Run's default startup probe.
+const START_TIMEOUT: Duration = Duration::from_secs(240);
+
+/// How often `--watch` looks for changed files.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What gets started: a binary built on this machine or an image built with docker or podman.
+enum Build {
+    Binary(PathBuf),
+    Image(Image),
+}
+
+/// Everything needed to build and start the service again.
+struct Service<'a> {
+    workspace: &'a Workspace,
+    pkg: &'a Value,
+    config: ServiceConfig,
+    env: Vec<(String, String)>,
+    args: &'a RunArgs,
+}
+
+/// Build the service and run it with the env Cloud Run would give it.
+pub fn handle_run(args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = if args.container { Workspace::load_locked()? } else { Workspace::load()? };
+    let pkg = workspace.find_root_package()?;
+    let mut config = ServiceConfig::load(pkg, args.environment.as_deref())?;
+    if !args.container {
+        // The image is built without sccache, its bucket is only reachable from Cloud Build
+        config.build.sccache = WorkspaceConfig::load(&workspace)?.sccache;
+    }
+
+    let env_file = match &args.env_file {
+        Some(path) => Some(path.clone()),
+        None => package_dir(pkg).map(|dir| dir.join(".env")).filter(|path| path.exists()),
+    };
+    let mut env = Vec::new();
+    if let Some(path) = &env_file {
+        env = local::read_env_file(path)?;
+        println!("Using env from {}", display(path));
+    }
+    env.extend(args.env.iter().cloned());
+
+    let port = match args.port {
+        Some(port) => port,
+        None => local::free_port()?,
+    };
+    let service = Service { workspace: &workspace, pkg, config, env, args };
+    if args.watch {
+        return watch(&service, port);
+    }
+
+    let build = service.build()?;
+    let mut running = service.start(&build, port)?;
+    println!("'{}' is listening on http://127.0.0.1:{port}", service.config.service);
+    let status = running.child.wait()?;
+    if !status.success() {
+        return Err(format!("'{}' exited with {status}", service.config.service).into());
+    }
+    Ok(())
+}
+
+impl Service<'_> {
+    fn build(&self) -> Result<Build, Box<dyn Error>> {
+        match self.args.container {
+            // The same staged context and Dockerfile `deploy` uploads
+            true => Ok(Build::Image(Image::build(self.workspace, self.pkg, &self.config)?)),
+            false => Ok(Build::Binary(local::build(self.workspace, self.pkg, &self.config.build)?)),
+        }
+    }
+
+    /// Start `build` listening on `port` and wait until it accepts connections.
+    fn start(&self, build: &Build, port: u16) -> Result<Running, Box<dyn Error>> {
+        let mut running = match build {
+            Build::Binary(binary) => {
+                let child = Command::new(binary)
+                    .args(&self.args.args)
+                    .envs(local::cloud_run_env(&self.config, port))
+                    .envs(self.env.iter().cloned())
+                    .stdin(Stdio::null())
+                    .spawn()
+                    .map_err(|err| format!("Failed to start {}: {err}", binary.display()))?;
+                Running { child, container: None }
+            }
+            Build::Image(image) => {
+                let name = format!("cloudrun-{}-{}", self.config.service, std::process::id());
+                let child = image
+                    .run_command(&self.config, port, &name, &self.env)?
+                    .args(&self.args.args)
+                    .stdin(Stdio::null())
+                    .spawn()
+                    .map_err(|err| format!("Failed to run {}: {err}", image.engine))?;
+                Running { child, container: Some((image.engine, name)) }
+            }
+        };
+
+        if let Err(err) = running.wait_for_port(port, START_TIMEOUT) {
+            running.stop();
+            return Err(err);
+        }
+        Ok(running)
+    }
+}
+
+/// Rebuild and restart the service whenever a file of the package or its path dependencies
+/// changes. The old process keeps serving until the new build succeeded.
+fn watch(service: &Service, port: u16) -> Result<(), Box<dyn Error>> {
+    let name = &service.config.service;
+    // With a recorder in front, the service listens on a port of its own
+    let (recorder, upstream) = if service.args.replay_event {
+        let upstream = local::free_port()?;
+        (Some(Recorder::start(port, upstream)?), upstream)
+    } else {
+        (None, port)
+    };
+
+    let mut stamps = file_stamps(service)?;
+    let mut running = restart(service, None, upstream);
+    if running.is_some() {
+        println!("'{name}' is listening on http://127.0.0.1:{port}, watching for changes");
+    }
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if let Some(status) = running.as_mut().and_then(|r| r.child.try_wait().ok().flatten()) {
+            println!("'{name}' exited with {status}, waiting for changes");
+            if let Some(running) = running.take() {
+                running.stop();
+            }
+        }
+
+        let mut current = file_stamps(service)?;
+        if current == stamps {
+            continue;
+        }
+        // Editors write several files in a row, wait until they are done
+        while current != stamps {
+            stamps = current;
+            thread::sleep(POLL_INTERVAL);
+            current = file_stamps(service)?;
+        }
+
+        println!("Files changed, rebuilding '{name}'");
+        running = restart(service, running, upstream);
+        if running.is_some() {
+            println!("'{name}' is listening on http://127.0.0.1:{port}");
+            if let Some(recorder) = &recorder {
+                recorder.replay();
+            }
+        }
+    }
+}
+
+/// Build the service and replace `running` with it. Errors are printed, and the old
+/// process keeps running if the build failed.
+fn restart(service: &Service, running: Option<Running>, port: u16) -> Option<Running> {
+    let build = match service.build() {
+        Ok(build) => build,
+        Err(err) => {
+            eprintln!("{err}, waiting for changes");
+            return running;
+        }
+    };
+    if let Some(running) = running {
+        running.stop();
+    }
+    match service.start(&build, port) {
+        Ok(running) => Some(running),
+        Err(err) => {
+            eprintln!("{err}, waiting for changes");
+            None
+        }
+    }
+}
+
+/// Modification time and size of every file the build reads.
+fn file_stamps(service: &Service) -> Result<BTreeMap<PathBuf, (SystemTime, u64)>, Box<dyn Error>> {
+    let mut stamps = BTreeMap::new();
+    for file in context::package_files(service.workspace, service.pkg)? {
+        // Files may disappear between listing and reading them
+        let Ok(metadata) = fs::metadata(&file) else { continue };
+        stamps.insert(file, (metadata.modified()?, metadata.len()));
+    }
+    Ok(stamps)
+}
+
+/// `path` relative to the current directory if it is below it.
+fn display(path: &Path) -> String {
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    path.strip_prefix(&current_dir).unwrap_or(path).display().to_string()
+}
@@ -0,0 +1,186 @@
+use crate::config::ServiceConfig;
+use crate::metadata::{package_name, Workspace};
+use serde_json::Value;
+use std::error::Error;
+
+/// A deployable workspace member together with its config.
+pub struct Service<'a> {
+    pub pkg: &'a Value,
+    pub config: ServiceConfig,
+}
+
+/// Every deployable member of the workspace, see [`Workspace::deployable_packages`], with
+/// the members named in `exclude` (by service or package name) unselected.
+/// Excluded members are kept so their dependents can look them up, unless they cannot be
+/// loaded for `environment`. Fails on names that match no member.
+pub fn load_excluding<'a>(
+    workspace: &'a Workspace,
+    environment: Option<&str>,
+    exclude: &[String],
+) -> Result<(Vec<Service<'a>>, Vec<bool>), Box<dyn Error>> {
+    let packages = workspace.deployable_packages()?;
+    // Service names without environment overrides, so excluded members need not define it
+    let names = packages
+        .iter()
+        .map(|pkg| Ok(ServiceConfig::load(pkg, None)?.service))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let matches = |name: &String, index: usize| *name == names[index] || name == package_name(packages[index]);
+    if let Some(name) = exclude.iter().find(|name| !(0..packages.len()).any(|index| matches(name, index))) {
+        return Err(format!("'{name}' passed to --exclude is not a deployable package of the workspace").into());
+    }
+    let excluded = (0..packages.len())
+        .map(|index| exclude.iter().any(|name| matches(name, index)))
+        .collect::<Vec<_>>();
+
+    let mut services = Vec::new();
+    let mut selected = Vec::new();
+    for (pkg, excluded) in packages.into_iter().zip(excluded) {
+        let config = match ServiceConfig::load(pkg, environment) {
+            Ok(config) => config,
+            Err(_) if excluded => continue,
+            Err(err) => return Err(err),
+        };
+        services.push(Service { pkg, config });
+        selected.push(!excluded);
+    }
+    Ok((services, selected))
+}
+
+/// Load the deployable member named `name` (by service or package name) for `environment`,
+/// without loading any other member for it.
+pub fn load_named<'a>(
+    workspace: &'a Workspace,
+    environment: Option<&str>,
+    name: &str,
+) -> Result<Option<Service<'a>>, Box<dyn Error>> {
+    for pkg in workspace.deployable_packages()? {
+        if package_name(pkg) == name || ServiceConfig::load(pkg, None)?.service == name {
+            let config = ServiceConfig::load(pkg, environment)?;
+            return Ok(Some(Service { pkg, config }));
+        }
+    }
+    Ok(None)
+}
+
+/// Find a service by its service or package name.
+pub fn position(services: &[Service], name: &str) -> Option<usize> {
+    services
+        .iter()
+        .position(|s| s.config.service == name || package_name(s.pkg) == name)
+}
+
+/// For each service, the indices of the services it declares in `depends-on`.
+/// Fails on unknown names and on dependency cycles.
+pub fn dependencies(services: &[Service]) -> Result<Vec<Vec<usize>>, Box<dyn Error>> {
+    let mut deps = Vec::with_capacity(services.len());
+    for service in services {
+        let mut indices = Vec::new();
+        for name in &service.config.depends_on {
+            let Some(index) = position(services, name) else {
+                return Err(format!(
+                    "'{}' depends on '{name}', which is not a deployable package of the workspace",
+                    service.config.service
+                )
+                .into());
+            };
+            indices.push(index);
+        }
+        deps.push(indices);
+    }
+
+    // Depth-first search for cycles
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        Visiting,
+        Done,
+    }
+    fn visit(i: usize, deps: &[Vec<usize>], marks: &mut [Mark], path: &mut Vec<usize>) -> Option<Vec<usize>> {
+        match marks[i] {
+            Mark::Done => return None,
+            Mark::Visiting => {
+                let start = path.iter().position(|&p| p == i).unwrap_or(0);
+                let mut cycle = path[start..].to_vec();
+                cycle.push(i);
+                return Some(cycle);
+            }
+            Mark::New => {}
+        }
+        marks[i] = Mark::Visiting;
+        path.push(i);
+        for &dep in &deps[i] {
+            if let Some(cycle) = visit(dep, deps, marks, path) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        marks[i] = Mark::Done;
+        None
+    }
+
+    let mut marks = vec![Mark::New; services.len()];
+    for i in 0..services.len() {
+        if let Some(cycle) = visit(i, &deps, &mut marks, &mut Vec::new()) {
+            let names = cycle
+                .iter()
+                .map(|&i| services[i].config.service.as_str())
+                .collect::<Vec<_>>();
+            return Err(format!("Dependency cycle between services: {}", names.join(" -> ")).into());
+        }
+    }
+    Ok(deps)
+}
+
+/// `cargo metadata` entries of packages named after their service, each with its `depends-on`.
+#[cfg(test)]
+pub(crate) fn test_packages(depends_on: &[(&str, &[&str])]) -> Vec<Value> {
+    depends_on
+        .iter()
+        .map(|(name, deps)| serde_json::json!({ "name": name, "metadata": { "cloudrun": { "depends-on": deps } } }))
+        .collect()
+}
+
+/// The services of `packages`, with their base config.
+#[cfg(test)]
+pub(crate) fn test_services(packages: &[Value]) -> Vec<Service<'_>> {
+    packages
+        .iter()
+        .map(|pkg| Service {
+            pkg,
+            config: ServiceConfig::load(pkg, None).unwrap(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_dependencies_to_indices() {
+        let packages = test_packages(&[("a", &["b", "c"]), ("b", &["c"]), ("c", &[])]);
+        let deps = dependencies(&test_services(&packages)).unwrap();
+        assert_eq!(deps, vec![vec![1, 2], vec![2], vec![]]);
+    }
+
+    #[test]
+    fn rejects_unknown_dependencies() {
+        let packages = test_packages(&[("a", &["missing"])]);
+        let err = dependencies(&test_services(&packages)).unwrap_err().to_string();
+        assert!(err.contains("'a' depends on 'missing'"), "{err}");
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let packages = test_packages(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &[])]);
+        let err = dependencies(&test_services(&packages)).unwrap_err().to_string();
+        assert_eq!(err, "Dependency cycle between services: a -> b -> c -> a");
+    }
+
+    #[test]
+    fn rejects_self_dependencies() {
+        let packages = test_packages(&[("a", &["a"])]);
+        let err = dependencies(&test_services(&packages)).unwrap_err().to_string();
+        assert_eq!(err, "Dependency cycle between services: a -> a");
+    }
+}
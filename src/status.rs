@@ -0,0 +1,177 @@
+use crate::config::ServiceConfig;
+use crate::metadata::Workspace;
+use crate::{gcloud, git, table, ListArgs, StatusArgs};
+use serde_json::Value;
+use std::error::Error;
+use std::thread;
+
+/// Show the Cloud Run state of every deployable package in the workspace.
+pub fn handle_status(args: &StatusArgs) -> Result<(), Box<dyn Error>> {
+    let workspace = Workspace::load()?;
+    let configs = workspace
+        .deployable_packages()?
+        .into_iter()
+        .map(|pkg| ServiceConfig::load(pkg, args.environment.as_deref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Describing services is slow, so ask about all of them at once
+    let results = thread::scope(|scope| {
+        let handles = configs
+            .iter()
+            .map(|config| scope.spawn(move || status_row(config)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("status thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (config, result) in configs.iter().zip(results) {
+        match result {
+            Ok(row) => rows.push(row),
+            Err(err) => {
+                rows.push(vec![config.service.clone(), "error".to_string()]);
+                errors.push(format!("{}: {err}", config.service));
+            }
+        }
+    }
+
+    table::print(
+        &["SERVICE", "READY", "URL", "LATEST REVISION", "TRAFFIC", "DEPLOYED", "GIT SHA"],
+        &rows,
+    );
+    for error in errors {
+        eprintln!("{error}");
+    }
+    Ok(())
+}
+
+/// List every Cloud Run service in the project/region.
+pub fn handle_list(args: &ListArgs) -> Result<(), Box<dyn Error>> {
+    let mut cmd_args = vec!["run".to_string(), "services".to_string(), "list".to_string()];
+    if let Some(project) = &args.project {
+        cmd_args.push(format!("--project={project}"));
+    }
+    if let Some(region) = &args.region {
+        cmd_args.push(format!("--region={region}"));
+    }
+    let services = gcloud::json(&cmd_args)?;
+
+    let rows = services
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|service| {
+            vec![
+                str_at(service, "/metadata/name"),
+                str_at(service, "/metadata/labels/cloud.googleapis.com~1location"),
+                ready_text(service),
+                str_at(service, "/status/url"),
+                str_at(service, "/status/latestReadyRevisionName"),
+                timestamp(&ready_transition(service)),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    table::print(&["SERVICE", "REGION", "READY", "URL", "LATEST REVISION", "LAST CHANGE"], &rows);
+    Ok(())
+}
+
+fn status_row(config: &ServiceConfig) -> Result<Vec<String>, String> {
+    let flags = config.target_flags();
+    let service = match gcloud::describe_service(&config.service, &flags) {
+        Ok(service) => service,
+        Err(err) if err.to_string().contains("could not be found") => {
+            return Ok(vec![config.service.clone(), "not deployed".to_string()]);
+        }
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let latest = str_at(&service, "/status/latestReadyRevisionName");
+    let (deployed, sha) = match gcloud::describe_revision(&latest, &flags) {
+        Ok(revision) => (
+            timestamp(&str_at(&revision, "/metadata/creationTimestamp")),
+            revision
+                .pointer(&format!("/metadata/labels/{}", git::SHA_LABEL))
+                .and_then(Value::as_str)
+                .unwrap_or("-")
+                .to_string(),
+        ),
+        Err(_) => ("-".to_string(), "-".to_string()),
+    };
+
+    Ok(vec![
+        config.service.clone(),
+        ready_text(&service),
+        str_at(&service, "/status/url"),
+        latest,
+        traffic_split(&service),
+        deployed,
+        sha,
+    ])
+}
+
+/// e.g. `my-svc-00012-abc 90%, my-svc-00011-xyz 10% (canary)`
+fn traffic_split(service: &Value) -> String {
+    let Some(traffic) = service.pointer("/status/traffic").and_then(Value::as_array) else {
+        return "-".to_string();
+    };
+    traffic
+        .iter()
+        .filter_map(|target| {
+            let percent = target.get("percent").and_then(Value::as_u64).unwrap_or(0);
+            let tag = target.get("tag").and_then(Value::as_str);
+            if percent == 0 && tag.is_none() {
+                return None;
+            }
+            let revision = target
+                .get("revisionName")
+                .and_then(Value::as_str)
+                .unwrap_or("LATEST");
+            Some(match tag {
+                Some(tag) => format!("{revision} {percent}% ({tag})"),
+                None => format!("{revision} {percent}%"),
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn ready_text(service: &Value) -> String {
+    match gcloud::ready_condition(service) {
+        (status, _) if status == "True" => "ready".to_string(),
+        (status, message) if status == "False" => format!("failed: {message}"),
+        _ => "deploying".to_string(),
+    }
+}
+
+fn ready_transition(service: &Value) -> String {
+    service
+        .pointer("/status/conditions")
+        .and_then(Value::as_array)
+        .and_then(|c| c.iter().find(|c| c.get("type").and_then(Value::as_str) == Some("Ready")))
+        .and_then(|c| c.get("lastTransitionTime"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// `2024-05-01T10:00:00.123456Z` -> `2024-05-01 10:00:00Z`
+fn timestamp(rfc3339: &str) -> String {
+    if rfc3339.is_empty() {
+        return "-".to_string();
+    }
+    let trimmed = rfc3339.split('.').next().unwrap_or(rfc3339).trim_end_matches('Z');
+    format!("{}Z", trimmed.replacen('T', " ", 1))
+}
+
+fn str_at(value: &Value, pointer: &str) -> String {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .unwrap_or("-")
+        .to_string()
+}
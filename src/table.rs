@@ -0,0 +1,25 @@
+/// Print `rows` as a left-aligned table with a header line.
+pub fn print(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths = headers.iter().map(|h| h.len()).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
@@ -32,8 +32,9 @@ async fn main() {
 
     // Build and run the application
     let app = echo_app();
-    // run it
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+    // run it on the port Cloud Run (or `cargo cloudrun run`) gives us
+    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await.unwrap();
     println!("listening on {}", listener.local_addr().unwrap());
     axum::serve(listener, app).await.unwrap();
 }
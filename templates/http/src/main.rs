@@ -12,8 +12,9 @@ async fn main() {
     // build our application with a route
     let app = Router::new().route("/", get(handler));
 
-    // run it
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080")
+    // run it on the port Cloud Run (or `cargo cloudrun run`) gives us
+    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
         .await
         .unwrap();
     println!("listening on {}", listener.local_addr().unwrap());